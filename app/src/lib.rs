@@ -1,8 +1,8 @@
 use key::{
   db::{get_database, get_database_key, KeeOptions},
-  NodeRef,
+  get_entry_detail, KeyEntryDetail,
 };
-use std::{env, sync::Mutex};
+use std::sync::Mutex;
 use tauri::{AppHandle, Manager};
 
 #[derive(serde::Serialize)]
@@ -25,16 +25,16 @@ impl From<key::Entry> for Entry {
 }
 
 #[tauri::command]
-async fn entry(app: AppHandle, name: String) -> Result<Entry, String> {
+async fn entry(
+  app: AppHandle,
+  name: String,
+  reveal: Option<bool>,
+) -> Result<KeyEntryDetail, String> {
   let state = app.state::<Mutex<AppState>>();
-  let mut s = state.lock().unwrap();
-  let db = s.db.as_mut().unwrap();
+  let s = state.lock().unwrap();
+  let db = s.db.as_ref().ok_or("Database is locked")?;
 
-  if let Some(NodeRef::Entry(e)) = db.root.clone().get(&[name.as_str()]) {
-    return Ok(Entry::from(e.clone()));
-  } else {
-    return Err("Cant find entry".into());
-  }
+  get_entry_detail(db, &name, reveal.unwrap_or(false)).map_err(|e| e.to_string())
 }
 
 #[tauri::command]
@@ -48,7 +48,7 @@ async fn set_entry_field(
   let mut s = state.lock().unwrap();
   let db = s.db.as_mut().unwrap();
 
-  if let Some(NodeRef::Entry(e)) = db.root.clone().get(&[name.as_str()]) {
+  if let Some(e) = db.root.clone().entry_by_name(&name) {
     let entry = Entry::from(e.clone());
   } else {
     return Err("Cant find entry".into());
@@ -68,7 +68,7 @@ async fn get_entry_field(
   let db = s.db.clone();
 
   if let Some(db) = db {
-    if let Some(NodeRef::Entry(e)) = db.root.get(&[name.as_str()]) {
+    if let Some(e) = db.root.entry_by_name(&name) {
       let entry = e.clone();
       return Ok(entry.get(field.as_str()).map(|v| v.to_string()));
     } else {
@@ -95,7 +95,7 @@ async fn list(app: AppHandle) -> Result<String, ()> {
 async fn unlock(app: AppHandle, password: String) -> Result<(), String> {
   let state = app.state::<Mutex<AppState>>();
 
-  let mut options: KeeOptions = env::vars().into();
+  let mut options = KeeOptions::from_env().map_err(|e| e.to_string())?;
   options.keepassdb_password = Some(password);
 
   let key = if let Ok(key) = get_database_key(&options) {
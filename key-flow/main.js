@@ -45,14 +45,14 @@ on('query', async (params) => {
       title: entry.title,
       subtitle: entry.user,
       method: "copy_to_clipboard",
-      params: [entry.title],
+      params: [`uuid:${entry.uuid}`],
       iconPath: "Images\\key.png",
     };
   }))
 });
 
-on('copy_to_clipboard', async (title) => {
-  const data = await execkey("get", [title]);
+on('copy_to_clipboard', async (name) => {
+  const data = await execkey("get", [name]);
   cp.copy(data);
   return;
 });
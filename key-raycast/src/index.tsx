@@ -94,7 +94,7 @@ const execkey = async (command: string, args: string[]) => {
 };
 
 const copyOtpToClipboard = async (entry: Entry) => {
-  const otp = await execkey("otp", [entry.title]);
+  const otp = await execkey("read", [`key://uuid:${entry.uuid}?otp`]);
   await Clipboard.copy(otp);
   await showHUD("Copied to clipboard", {
     clearRootSearch: true,
@@ -103,7 +103,7 @@ const copyOtpToClipboard = async (entry: Entry) => {
 };
 
 const copyPasswordToClipboard = async (entry: Entry) => {
-  const pw = await execkey("get", [entry.title]);
+  const pw = await execkey("read", [`key://uuid:${entry.uuid}/Password`]);
   await Clipboard.copy(pw);
   await showHUD("Copied to clipboard", {
     clearRootSearch: true,
@@ -112,7 +112,7 @@ const copyPasswordToClipboard = async (entry: Entry) => {
 };
 
 const pastePassword = async (entry: Entry) => {
-  const pw = await execkey("get", [entry.title]);
+  const pw = await execkey("read", [`key://uuid:${entry.uuid}/Password`]);
   await Clipboard.paste(pw);
   await showHUD("Pasted Password", {
     clearRootSearch: true,
@@ -121,7 +121,7 @@ const pastePassword = async (entry: Entry) => {
 };
 
 const setField = async (entry: Entry, field: string, value: string) => {
-  await execkey("set", [entry.title, "--field", field, value]);
+  await execkey("set", [`uuid:${entry.uuid}`, "--field", field, value]);
 };
 
 export default function KeyCommand() {
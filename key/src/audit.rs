@@ -0,0 +1,207 @@
+use std::{
+  collections::{BTreeSet, HashMap, HashSet},
+  fs::File,
+  io::{BufRead, BufReader},
+  path::{Path, PathBuf},
+};
+
+use anyhow::{anyhow, Result};
+use chrono::NaiveDateTime;
+use keepass::db::Times;
+use serde::{Deserialize, Serialize};
+use sha1::{Digest, Sha1};
+
+use crate::{list_entries, Database, Entry};
+
+pub struct AuditOptions {
+  /// Passwords with a lower zxcvbn score (0-4) are reported as weak
+  pub min_score: u8,
+  /// Passwords unchanged for longer than this are reported as old
+  pub max_age_days: i64,
+  /// Offline Have I Been Pwned data, see [find_breached]
+  pub hibp: Option<PathBuf>,
+}
+
+impl Default for AuditOptions {
+  fn default() -> Self {
+    AuditOptions {
+      min_score: 3,
+      max_age_days: 365,
+      hibp: None,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum KeyAuditIssue {
+  /// zxcvbn score from 0 (very weak) to 4 (strong)
+  Weak { score: u8 },
+  /// Paths of the other entries using the same password
+  Reused { with: Vec<String> },
+  /// Days since the password was last changed
+  Old { days: i64, changed: NaiveDateTime },
+  /// Number of times the password appears in the breach data
+  Breached { count: u64 },
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct KeyAuditItem {
+  pub uuid: String,
+  pub path: String,
+  pub issues: Vec<KeyAuditIssue>,
+}
+
+/// Checks the password of every entry outside the recycle bin. Only entries
+/// with at least one issue are returned.
+pub fn audit_entries(db: &Database, options: &AuditOptions) -> Result<Vec<KeyAuditItem>> {
+  let entries: Vec<(String, &Entry, &str)> = list_entries(db)
+    .into_iter()
+    .filter_map(|(path, e)| {
+      let password = e.get_password().filter(|p| !p.is_empty())?;
+      Some((path, e, password))
+    })
+    .collect();
+
+  let mut by_password: HashMap<&str, Vec<&str>> = HashMap::new();
+  for (path, _, password) in entries.iter() {
+    by_password.entry(password).or_default().push(path);
+  }
+
+  let hashes: HashSet<String> = entries.iter().map(|(_, _, p)| sha1_hex(p)).collect();
+  let breached = match &options.hibp {
+    Some(path) => find_breached(path, &hashes)?,
+    None => HashMap::new(),
+  };
+
+  let now = Times::now();
+  let mut items = Vec::new();
+  for (path, entry, password) in entries.iter() {
+    let mut issues = Vec::new();
+
+    let user_inputs: Vec<&str> = [entry.get_title(), entry.get_username()]
+      .into_iter()
+      .flatten()
+      .collect();
+    let score = u8::from(zxcvbn::zxcvbn(password, &user_inputs).score());
+    if score < options.min_score {
+      issues.push(KeyAuditIssue::Weak { score });
+    }
+
+    let with: Vec<String> = by_password[password]
+      .iter()
+      .filter(|p| *p != path)
+      .map(|p| p.to_string())
+      .collect();
+    if !with.is_empty() {
+      issues.push(KeyAuditIssue::Reused { with });
+    }
+
+    if let Some(changed) = password_changed(entry) {
+      let days = (now - changed).num_days();
+      if days > options.max_age_days {
+        issues.push(KeyAuditIssue::Old { days, changed });
+      }
+    }
+
+    if let Some(count) = breached.get(&sha1_hex(password)) {
+      issues.push(KeyAuditIssue::Breached { count: *count });
+    }
+
+    if !issues.is_empty() {
+      items.push(KeyAuditItem {
+        uuid: entry.uuid.to_string(),
+        path: path.clone(),
+        issues,
+      });
+    }
+  }
+
+  Ok(items)
+}
+
+/// When the current password was set: the modification time of the oldest
+/// version in the history that already had it.
+fn password_changed(entry: &Entry) -> Option<NaiveDateTime> {
+  let password = entry.get_password();
+  let mut changed = entry.times.last_modification;
+  if let Some(history) = &entry.history {
+    for version in history.get_entries().iter() {
+      if version.get_password() != password {
+        break;
+      }
+      changed = version.times.last_modification.or(changed);
+    }
+  }
+  changed
+}
+
+fn sha1_hex(password: &str) -> String {
+  Sha1::digest(password.as_bytes())
+    .iter()
+    .map(|b| format!("{:02X}", b))
+    .collect()
+}
+
+/// Looks up SHA-1 hashes in offline Have I Been Pwned data and returns the
+/// breach count of every hash found. `path` can be
+/// - a file of full hashes ("HASH:COUNT" per line), like the ordered dump,
+/// - a k-anonymity range file named after its prefix ("21BD1.txt") with
+///   "SUFFIX:COUNT" lines,
+/// - a directory of such range files, as created by the PwnedPasswordsDownloader.
+pub fn find_breached(
+  path: &Path,
+  hashes: &HashSet<String>,
+) -> Result<HashMap<String, u64>> {
+  let mut found = HashMap::new();
+
+  if path.is_dir() {
+    let prefixes: BTreeSet<&str> = hashes.iter().map(|h| &h[..5]).collect();
+    for prefix in prefixes {
+      let file = [format!("{}.txt", prefix), prefix.to_string()]
+        .iter()
+        .map(|name| path.join(name))
+        .find(|file| file.is_file());
+      if let Some(file) = file {
+        scan_hibp_file(&file, prefix, hashes, &mut found)?;
+      }
+    }
+    return Ok(found);
+  }
+
+  let prefix = path
+    .file_stem()
+    .and_then(|stem| stem.to_str())
+    .filter(|stem| stem.len() == 5 && stem.chars().all(|c| c.is_ascii_hexdigit()))
+    .unwrap_or("")
+    .to_uppercase();
+  scan_hibp_file(path, &prefix, hashes, &mut found)?;
+  Ok(found)
+}
+
+fn scan_hibp_file(
+  path: &Path,
+  prefix: &str,
+  hashes: &HashSet<String>,
+  found: &mut HashMap<String, u64>,
+) -> Result<()> {
+  let file = File::open(path)
+    .map_err(|err| anyhow!("Can not read HIBP data {}: {}", path.display(), err))?;
+
+  for line in BufReader::new(file).lines() {
+    let line = line?;
+    let (hash, count) = match line.trim().split_once(':') {
+      Some(parts) => parts,
+      None => continue,
+    };
+    let hash = match hash.len() {
+      40 => hash.to_uppercase(),
+      35 if !prefix.is_empty() => format!("{}{}", prefix, hash.to_uppercase()),
+      _ => continue,
+    };
+    if hashes.contains(&hash) {
+      found.insert(hash, count.trim().parse().unwrap_or(1));
+    }
+  }
+  Ok(())
+}
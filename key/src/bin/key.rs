@@ -1,18 +1,52 @@
 extern crate copypasta;
 
-use anyhow::Result;
-use clap::{CommandFactory, Parser, Subcommand};
+use anyhow::{anyhow, Result};
+use chrono::NaiveDateTime;
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::{
+  env::Shells, ArgValueCompleter, CompleteEnv, CompletionCandidate, Shell,
+};
 use colored::Colorize;
 use copypasta::{ClipboardContext, ClipboardProvider};
 use demand::{DemandOption, Input, Select};
-use keepass::{db::Node, Database, DatabaseKey};
+use keepass::{db::Times, Database, DatabaseKey};
+use key::audit::{audit_entries, AuditOptions, KeyAuditIssue};
+use key::config::{Config, Profile};
+use key::export::{export_entries, ExportFormat, ExportOptions};
+use key::import::{import_entries, read_import, ImportFormat, ImportStatus};
+use key::output::{render, render_error, Format};
+use key::ssh::{entry_ssh_key, parse_lifetime, ssh_keys, KeyAgent};
+use key::tui::{run_tui, DEFAULT_LOCK_TIMEOUT};
 use key::{
-  db::{create_database, get_database, write_database, KeeOptions},
-  delete_entry, get_entry, get_entry_file, get_entry_otp, rename_entry, to_json,
+  add_tag, check_template, copy_entry, create_group,
+  db::{
+    create_database, database_exists, get_cache_database, get_database, write_database,
+    KeeOptions,
+  },
+  delete_entry, delete_group, empty_trash, entry_expiry, erase_docker_credential,
+  erase_git_credential, expiring_entries, extract_group, find_entry,
+  get_docker_credential, get_entry, get_entry_attachment, get_entry_detail,
+  get_entry_history, get_entry_otp, get_entry_otp_details, get_git_credential, get_group,
+  has_tag, is_expired, key_from, list_docker_credentials, list_entries, list_groups,
+  list_trash, move_entry, move_group, new_entry, parse_expiry, remove_tag, rename_entry,
+  rename_group, render_template, restore_entry, restore_trash_entry, search_entries,
+  set_entry_expiry, store_docker_credential, store_git_credential, to_json, walk_entries,
+  ChangeKind, DockerCredential, Entry, GitCredential, KeyEntry, KeyEntryDetail,
+  KeyFieldChange, KeyReference, PasswordPolicy, ReferenceTarget,
+  DOCKER_CREDENTIALS_NOT_FOUND, REFERENCE_SCHEME, UUID_PREFIX,
 };
-use key::{generate_password, set_entry};
+use key::{generate_passphrase, generate_password, set_entry, PassphraseOptions};
 use log::debug;
-use std::{env, fmt, fs::File};
+use sha1::{Digest, Sha1};
+use std::{
+  env,
+  ffi::{OsStr, OsString},
+  fmt, fs,
+  fs::File,
+  io::{self, Read, Write},
+  path::{Path, PathBuf},
+  process::{self, ExitStatus},
+};
 use url::Url;
 
 /// Command Line Interface to a local or remote keepass database.
@@ -23,9 +57,15 @@ struct Cli {
   #[arg(short = 'k', long, env = "KEY_KEYFILE")]
   keyfile: Option<String>,
 
-  /// Url to the keepass database file (supports file:// and s3:// schemas)
-  #[arg(long, env = "KEY_DATABASE_URL")]
-  kdbx: Option<String>,
+  /// Url to the keepass database file (supports file:// and s3:// schemas).
+  /// List and search take several [env: KEY_DATABASE_URL]
+  #[arg(long)]
+  kdbx: Vec<String>,
+
+  /// Profile of ~/.config/key/config.toml to use, its url is used over
+  /// KEY_DATABASE_URL. List and search take several
+  #[arg(long, env = "KEY_PROFILE", value_delimiter = ',')]
+  profile: Vec<String>,
 
   /// Database password [env: KEY_PASSWORD]
   #[arg(short = 'p', long)]
@@ -39,6 +79,10 @@ struct Cli {
   #[arg(long)]
   s3_secret_key: Option<String>,
 
+  /// Output format
+  #[arg(long, global = true, value_enum, default_value_t = Format::Text)]
+  format: Format,
+
   #[command(subcommand)]
   command: Option<Commands>,
 }
@@ -46,8 +90,9 @@ struct Cli {
 #[derive(Subcommand)]
 enum Commands {
   /// Generate a One time password
-  OTP {
+  Otp {
     /// Name of entry
+    #[arg(add = ArgValueCompleter::new(complete_entry))]
     name: String,
 
     /// Field to get
@@ -64,6 +109,30 @@ enum Commands {
     /// Length of password
     #[arg(long, default_value = "18")]
     length: usize,
+
+    /// Generate a diceware passphrase instead of random characters
+    #[arg(long)]
+    passphrase: bool,
+
+    /// Number of words in the passphrase
+    #[arg(long, default_value = "6", requires = "passphrase")]
+    words: usize,
+
+    /// Separator between passphrase words
+    #[arg(long, default_value = "-", requires = "passphrase")]
+    separator: String,
+
+    /// Capitalise every passphrase word
+    #[arg(long, requires = "passphrase")]
+    capitalize: bool,
+
+    /// Append a random digit to one of the passphrase words
+    #[arg(long, requires = "passphrase")]
+    number: bool,
+
+    /// Path to a custom wordlist (one word per line, diceware format supported)
+    #[arg(long, requires = "passphrase")]
+    wordlist: Option<String>,
   },
 
   /// Create a new database
@@ -71,17 +140,49 @@ enum Commands {
 
   /// List all entries of the database
   List {
-    /// Output format (json, yaml, toml)
-    #[arg(short = 'o', long)]
-    output: Option<String>,
+    /// Deprecated, use --format. "json" keeps printing the legacy tree
+    #[arg(short = 'o', long, value_enum)]
+    output: Option<Format>,
+
+    /// Only list entries with this tag, can be given multiple times
+    #[arg(long)]
+    tag: Vec<String>,
+  },
+
+  /// Search entries by path, username or url
+  Search {
+    /// Text to search for
+    query: String,
+  },
+
+  /// Copy an entry into another vault
+  ///
+  /// Vaults are profile names or database urls. The copy keeps all fields,
+  /// including the OTP secret, and the attachments, but not the history.
+  Cp {
+    /// Name of entry
+    #[arg(add = ArgValueCompleter::new(complete_entry))]
+    name: String,
+
+    /// Name of the copy, the same path if not given
+    new_name: Option<String>,
+
+    /// Vault to copy from, the selected one if not given
+    #[arg(long)]
+    from: Option<String>,
+
+    /// Vault to copy to
+    #[arg(long)]
+    to: String,
   },
 
   /// Get a specific entry from the database
   Get {
     /// Name of entry
+    #[arg(add = ArgValueCompleter::new(complete_entry))]
     name: String,
 
-    /// Extract as file
+    /// Write the attachment named by --field to stdout
     #[arg(long)]
     file: bool,
 
@@ -89,38 +190,138 @@ enum Commands {
     #[arg(long)]
     clipboard: bool,
 
-    /// Field to get
-    #[arg(long, default_value = "Password")]
-    field: String,
+    /// Field to get [default: Password, or the field of the profile]
+    #[arg(long)]
+    field: Option<String>,
+  },
+
+  /// Show all details of an entry
+  Show {
+    /// Name of entry
+    name: String,
+
+    /// Show the values of protected fields
+    #[arg(long)]
+    reveal: bool,
+  },
+
+  /// List previous versions of an entry
+  #[command(args_conflicts_with_subcommands = true, subcommand_negates_reqs = true)]
+  History {
+    #[command(subcommand)]
+    command: Option<HistoryCommands>,
+
+    /// Name of entry
+    #[arg(required = true)]
+    name: Option<String>,
+
+    /// Show which fields changed in every version
+    #[arg(long)]
+    diff: bool,
   },
 
   /// Set the value of a specific entry in the database
   Set {
     /// Name of entry
+    #[arg(add = ArgValueCompleter::new(complete_entry))]
     name: String,
-    /// Password to set
-    value: String,
+    /// Password to set ("-" reads it from stdin)
+    #[arg(required_unless_present_any = ["generate", "prompt", "from_file"])]
+    value: Option<String>,
 
-    /// Field to set
-    #[arg(long, default_value = "Password")]
-    field: String,
+    /// Field to set [default: Password, or the field of the profile]
+    #[arg(long)]
+    field: Option<String>,
+
+    /// Generate the value instead (policy: chars[:LENGTH] or words[:COUNT])
+    #[arg(long, value_name = "POLICY", num_args = 0..=1, conflicts_with_all = ["value", "prompt", "from_file"])]
+    generate: Option<Option<String>>,
+
+    /// Prompt for the value with hidden input
+    #[arg(long, conflicts_with_all = ["value", "from_file"])]
+    prompt: bool,
+
+    /// Read the value from a file
+    #[arg(long, value_name = "PATH", conflicts_with = "value")]
+    from_file: Option<String>,
+
+    /// Print the generated value
+    #[arg(long, requires = "generate")]
+    print: bool,
+
+    /// Copy the generated value to system clipboard
+    #[arg(long, requires = "generate")]
+    clipboard: bool,
+  },
+
+  /// Create a new entry in the database
+  New {
+    /// Name of entry
+    name: String,
+
+    /// Username of the entry
+    #[arg(long)]
+    user: Option<String>,
+
+    /// Url of the entry
+    #[arg(long)]
+    url: Option<String>,
+
+    /// Generate a password (policy: chars[:LENGTH] or words[:COUNT])
+    #[arg(long, value_name = "POLICY", num_args = 0..=1)]
+    generate: Option<Option<String>>,
+
+    /// Print the generated password
+    #[arg(long, requires = "generate")]
+    print: bool,
+
+    /// Copy the generated password to system clipboard
+    #[arg(long, requires = "generate")]
+    clipboard: bool,
   },
 
-  /// Delete a specific entry from the database
+  /// Move an entry to the recycle bin
   Delete {
     /// Name of entry
+    #[arg(add = ArgValueCompleter::new(complete_entry))]
     name: String,
+
+    /// Delete the entry for good instead of moving it to the recycle bin
+    #[arg(long)]
+    permanent: bool,
+  },
+
+  /// List, restore or empty the recycle bin
+  Trash {
+    #[command(subcommand)]
+    command: TrashCommands,
   },
 
   /// Rename a specific entry in the database
   Rename {
     /// Name of entry
+    #[arg(add = ArgValueCompleter::new(complete_entry))]
     name: String,
 
     /// New name of entry
     new_name: String,
   },
 
+  /// Move an entry into another group
+  Mv {
+    /// Name of entry
+    name: String,
+
+    /// Path of the group to move it to ("/" for the root group)
+    group: String,
+  },
+
+  /// Create, rename, delete, move or list groups
+  Group {
+    #[command(subcommand)]
+    command: GroupCommands,
+  },
+
   /// Chooser terminal ui
   Choose {
     /// Copy value to system clipboard
@@ -131,315 +332,2236 @@ enum Commands {
     #[arg(long)]
     otp: bool,
 
-    /// Field to get
-    #[arg(long, default_value = "Password")]
-    field: String,
+    /// Field to get [default: Password, or the field of the profile]
+    #[arg(long)]
+    field: Option<String>,
+
+    /// Only offer entries with this tag, can be given multiple times
+    #[arg(long)]
+    tag: Vec<String>,
   },
-}
 
-fn options_from_cli(cli: &Cli) -> Result<KeeOptions> {
-  let keepassdb = cli.kdbx.clone();
-  let keepassdb_keyfile = cli.keyfile.clone();
-  let keepassdb_password = cli.password.clone().or(env::var("KEY_PASSWORD").ok());
-  let s3_access_key = cli
-    .s3_access_key
-    .clone()
-    .or(env::var("KEY_S3_ACCESS_KEY").ok());
-  let s3_secret_key = cli
-    .s3_secret_key
-    .clone()
-    .or(env::var("KEY_S3_SECRET_KEY").ok());
+  /// Browse and edit the database in a full screen terminal ui
+  Tui {
+    /// Lock after this many seconds without input, 0 never locks [default: 300, or
+    /// the lock_timeout of the profile]
+    #[arg(long)]
+    lock_after: Option<u64>,
+  },
 
-  if keepassdb.is_none() {
-    return Err(anyhow::format_err!("No database url provided."));
-  }
+  /// Set when an entry expires
+  SetExpiry {
+    /// Name of entry
+    name: String,
 
-  Ok(KeeOptions {
-    keepassdb: keepassdb.unwrap(),
-    keepassdb_keyfile,
-    keepassdb_password,
-    s3_access_key,
-    s3_secret_key,
-  })
-}
+    /// Date (2025-12-31), period from now (+90d, +12w, +6m, +1y) or "never"
+    expiry: String,
+  },
 
-fn read_password(title: String) -> String {
-  let t = Input::new(title).placeholder("Password").password(true);
-  t.run().expect("error running input")
-}
+  /// List entries that have expired or expire soon. Fails if any have expired
+  Expiring {
+    /// Period to look ahead (30d, 12w, 6m, 1y)
+    #[arg(long, default_value = "30d")]
+    within: String,
+  },
 
-fn get_database_key(options: &KeeOptions) -> Result<DatabaseKey> {
-  let dburl_parsed = Url::parse(&options.keepassdb.as_str())?;
-  let name = dburl_parsed.path().split('/').last().unwrap().to_string();
+  /// Report weak, reused, old and breached passwords. Fails if any are found
+  Audit {
+    /// Minimum zxcvbn score (0-4) a password needs to not be reported as weak
+    #[arg(long, default_value_t = 3, value_parser = clap::value_parser!(u8).range(0..=4))]
+    min_score: u8,
 
-  let mut key = DatabaseKey::new();
+    /// Report passwords unchanged for more than this many days
+    #[arg(long, default_value_t = 365)]
+    max_age: i64,
 
-  let keypath = &options.keepassdb_keyfile;
-  if let Some(keypath) = keypath {
-    key = key.with_keyfile(&mut File::open(keypath)?)?;
-  }
+    /// Offline Have I Been Pwned data: a SHA-1 hash file, a range file or a
+    /// directory of range files
+    #[arg(long)]
+    hibp: Option<PathBuf>,
+  },
 
-  let password = &options.keepassdb_password;
-  if let Some(password) = password {
-    key = key.with_password(password.as_str())
-  } else {
-    key = key.with_password(read_password(format!("Password for {}", name)).as_str());
-  }
+  /// Import entries from another password manager or database
+  Import {
+    /// File to import
+    file: String,
 
-  Ok(key)
-}
+    /// Format of the file. Not --format, which sets the output format
+    #[arg(long, value_enum)]
+    from: ImportFormat,
 
-async fn command_list(options: &KeeOptions, format: &str) -> Result<()> {
-  let db = get_database(&options, &get_database_key(&options)?).await?;
+    /// Group to import into ("Team/Imported")
+    #[arg(long, default_value = "")]
+    group: String,
 
-  match format {
-    "json" => {
-      println!("{}", to_json(db)?);
-    }
-    _ => {
-      for entry in db.root.children.iter() {
-        match entry {
-          Node::Group(g) => {
-            for child in g.children.iter() {
-              match child {
-                Node::Entry(e) => {
-                  println!("{}/{}", g.name, e.get_title().unwrap().to_string());
-                }
-                _ => continue,
-              }
-            }
-          }
-          Node::Entry(e) => {
-            println!("{}", e.get_title().unwrap().to_string());
-          }
-        };
-      }
-    }
-  }
+    /// Only show what would be imported
+    #[arg(long)]
+    dry_run: bool,
 
-  Ok(())
-}
+    /// Import entries without the attachments the export does not contain,
+    /// like those of Bitwarden JSON exports
+    #[arg(long)]
+    skip_attachments: bool,
 
-struct ChooseEntry {
-  value: String,
-  user: Option<String>,
-}
+    /// Keyfile of the database to import, for kdbx [password env: KEY_SOURCE_PASSWORD]
+    #[arg(long)]
+    source_keyfile: Option<String>,
+  },
 
-impl fmt::Display for ChooseEntry {
-  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    write!(
-      f,
-      "{} ({})",
-      self.value,
-      self.user.clone().unwrap_or("".to_string()).bright_black()
-    )
-  }
-}
+  /// Export entries. Secrets are only included with --include-secrets
+  Export {
+    /// File to write to, "-" for stdout
+    out: String,
 
-fn choose_key_ui(db: &Database) -> ChooseEntry {
-  let ms: Select<ChooseEntry> = Select::new("Keys")
-    .description("Select a key")
-    .filterable(true);
+    /// Format of the export. Not --format, which sets the output format
+    #[arg(long, value_enum)]
+    to: ExportFormat,
 
-  let mut options: Vec<DemandOption<ChooseEntry>> = Vec::new();
+    /// Include passwords, OTP secrets and other protected fields
+    #[arg(long)]
+    include_secrets: bool,
 
-  db.root.children.iter().for_each(|entry| match entry {
-    Node::Entry(e) => {
-      options.push(DemandOption::new(ChooseEntry {
-        user: Some(e.get_username().unwrap_or("").to_string()),
-        value: e.get_title().unwrap().to_string(),
-      }));
-    }
-    Node::Group(g) => {
-      for child in g.children.iter() {
-        match child {
-          Node::Entry(e) => {
-            options.push(DemandOption::new(ChooseEntry {
-              user: Some(e.get_username().unwrap_or("").to_string()),
-              value: e.get_title().unwrap().to_string(),
-            }));
-          }
-          _ => continue,
-        }
-      }
-    }
-  });
+    /// Only export entries below this group ("Team/Shared")
+    #[arg(long, default_value = "")]
+    group: String,
 
-  ms.options(options).run().expect("error running select")
-}
+    /// Only export entries with this tag, can be repeated
+    #[arg(long)]
+    tag: Vec<String>,
 
-async fn command_choose(
-  options: &KeeOptions,
-  field: &String,
-  clipboard: &bool,
-  otp: &bool,
-) -> Result<()> {
-  let db = get_database(&options, &get_database_key(&options)?).await?;
+    /// Save a kdbx export under a new password [env: KEY_EXPORT_PASSWORD]
+    #[arg(long)]
+    new_password: bool,
 
-  let entry = if otp.to_owned() {
-    get_entry_otp(&db, &choose_key_ui(&db).value, &"otp".to_string())?
-  } else {
-    get_entry(&db, &choose_key_ui(&db).value, &field)?
-  };
+    /// Save a kdbx export with this keyfile instead of the current key
+    #[arg(long)]
+    new_keyfile: Option<String>,
+  },
 
-  if clipboard.to_owned() {
-    to_clipboard(entry)?;
-    println!("Copied to clipboard");
-    return Ok(());
-  }
+  /// Copy a group with everything in it into a new database with its own key
+  Extract {
+    /// Group to extract ("Clients/Acme")
+    group: String,
 
-  println!("{}", entry);
-  Ok(())
-}
+    /// Url of the new database (file:// or s3://)
+    #[arg(long)]
+    to: String,
 
-fn to_clipboard(entry: String) -> Result<()> {
-  let mut ctx = ClipboardContext::new().unwrap();
-  ctx.set_contents(entry).unwrap();
-  return Ok(());
-}
+    /// Protect the new database with a password [env: KEY_EXTRACT_PASSWORD].
+    /// This is the default without --keyfile
+    #[arg(long)]
+    password: bool,
 
-async fn command_get(
-  options: &KeeOptions,
-  name: &String,
-  field: &String,
-  clipboard: &bool,
-) -> Result<()> {
-  let db = get_database(&options, &get_database_key(&options)?).await?;
-  let entry = get_entry(&db, name, field)?;
+    /// Protect the new database with this keyfile
+    #[arg(long)]
+    keyfile: Option<String>,
 
-  if clipboard.to_owned() {
-    to_clipboard(entry)?;
-    println!("Copied {field} to clipboard");
-    return Ok(());
-  }
+    /// Overwrite the database or S3 object that already is at --to
+    #[arg(long)]
+    force: bool,
+  },
 
-  println!("{}", entry);
-  Ok(())
-}
+  /// Run a command with secrets as environment variables
+  Run {
+    /// Set a variable to a field of an entry or a key:// reference, can be
+    /// repeated. The field defaults to Password, "otp" gives the current code
+    #[arg(long = "env", value_name = "NAME=ENTRY:FIELD")]
+    env: Vec<String>,
+
+    /// Set a variable for every entry in a group, named after its title and set
+    /// to its password, can be repeated
+    #[arg(long, value_name = "GROUP")]
+    env_group: Vec<String>,
+
+    /// Command to run, after "--"
+    #[arg(last = true, required = true)]
+    command: Vec<String>,
+  },
 
-async fn command_get_file(
-  options: &KeeOptions,
-  name: &String,
-  field: &String,
-) -> Result<()> {
-  let db = get_database(&options, &get_database_key(&options)?).await?;
-  get_entry_file(&db, name, field)?;
-  Ok(())
-}
+  /// Print the secret a reference points to
+  ///
+  /// References look like key://Group/Entry/Field, key://Group/Entry?otp or
+  /// key://uuid:<uuid>/Field
+  Read {
+    /// Reference to read
+    uri: String,
 
-async fn command_otp(
-  options: &KeeOptions,
-  name: &String,
-  field: &String,
-  clipboard: &bool,
-) -> Result<()> {
-  let db = get_database(&options, &get_database_key(&options)?).await?;
-  let val = get_entry_otp(&db, name, field)?;
+    /// Copy value to system clipboard
+    #[arg(long)]
+    clipboard: bool,
+  },
 
-  if clipboard.to_owned() {
-    to_clipboard(val)?;
-    println!("Copied {field} to clipboard");
-    return Ok(());
-  }
+  /// Fill a template with values from the database
+  ///
+  /// References look like {{ key "Group/Entry" "Field" }} or
+  /// key://Group/Entry/Field
+  Inject {
+    /// Template to read, stdin if not given
+    #[arg(short = 'i', long)]
+    input: Option<String>,
+
+    /// File to write to, stdout if not given
+    #[arg(short = 'o', long, conflicts_with = "check")]
+    output: Option<String>,
 
-  println!("{}", val);
-  Ok(())
-}
+    /// Only check that every reference resolves
+    #[arg(long)]
+    check: bool,
+  },
 
-async fn command_set(
-  options: &KeeOptions,
-  name: &String,
-  value: &String,
-  field: &String,
-) -> Result<()> {
-  let key = get_database_key(&options)?;
-  let mut db = get_database(&options, &key).await?;
-  set_entry(&mut db, name, value, field)?;
-  debug!("Set entry field {} to {}", field, value);
-  write_database(&options, &mut db, &key).await?;
-  Ok(())
-}
+  /// Git credential helper
+  ///
+  /// Set up with `git config --global credential.helper '!key git-credential'`
+  GitCredential {
+    /// What git asks for
+    #[arg(value_enum)]
+    operation: CredentialOperation,
+
+    /// Group new credentials are stored in
+    #[arg(long, default_value = "")]
+    group: String,
+  },
 
-async fn command_rename(
-  options: &KeeOptions,
-  name: &String,
-  new_name: &String,
-) -> Result<()> {
-  let key = get_database_key(&options)?;
-  let mut db = get_database(&options, &key).await?;
-  rename_entry(&mut db, name, new_name)?;
-  debug!("Set Title of field {} to {}", name, new_name);
-  write_database(&options, &mut db, &key).await?;
-  Ok(())
-}
+  /// Docker credential helper
+  ///
+  /// Link the key binary as docker-credential-key and set "credsStore": "key"
+  /// in ~/.docker/config.json
+  DockerCredential {
+    /// What docker asks for
+    #[arg(value_enum)]
+    operation: DockerCredentialOperation,
+
+    /// Group registry credentials are stored in
+    #[arg(long, env = "KEY_DOCKER_GROUP", default_value = "Docker")]
+    group: String,
+  },
 
-async fn command_create(path: &String) -> Result<()> {
-  let db = create_database()?;
+  /// Add the ssh key of an entry to the running ssh agent
+  ///
+  /// The OpenSSH private key is read from the notes or another field of the
+  /// entry and decrypted with the entry password if it is encrypted.
+  SshAdd {
+    /// Name of entry
+    name: String,
 
-  let mut key = DatabaseKey::new();
-  let pw = read_password("Create a password".to_string());
-  key = key.with_password(pw.as_str());
+    /// Field or attachment holding the key, the first one that holds a key if
+    /// not given
+    #[arg(long)]
+    field: Option<String>,
 
-  db.save(&mut File::create(path)?, key)?;
+    /// How long the agent keeps the key (90, 30m, 8h, 1d), 0 for no limit
+    #[arg(short = 't', long, default_value = "1h")]
+    lifetime: String,
+  },
 
-  Ok(())
+  /// Serve the ssh keys of entries as ssh agent
+  ///
+  /// Prints the environment to use the agent, e.g. run with
+  /// `eval $(key ssh-agent &)`
+  SshAgent {
+    /// Entries to serve, all entries that hold an ssh key if not given
+    names: Vec<String>,
+
+    /// Socket to listen on, a new one in the temp directory if not given
+    #[arg(short = 'a', long)]
+    socket: Option<PathBuf>,
+  },
+
+  /// Print the script that sets up shell completions
+  ///
+  /// Load it with e.g. `source <(key completions bash)`. Entry names are
+  /// completed from the database in KEY_DATABASE_URL or the profile, opened
+  /// with KEY_PASSWORD or a keyfile. S3 databases are read from the local cache.
+  Completions {
+    #[arg(value_enum)]
+    shell: Shell,
+  },
+
+  /// Clear the clipboard after a delay, used for the clipboard timeout
+  #[command(hide = true)]
+  ClearClipboard {
+    /// Seconds to wait
+    #[arg(long)]
+    after: u64,
+  },
+
+  /// Add or remove tags of an entry
+  Tag {
+    #[command(subcommand)]
+    command: TagCommands,
+  },
 }
 
-async fn command_delete(options: &KeeOptions, name: &String) -> Result<()> {
-  let key = get_database_key(&options)?;
-  let mut db = get_database(&options, &key).await?;
-  delete_entry(&mut db, name)?;
-  debug!("Deleted entry {}", name);
-  write_database(&options, &mut db, &key).await?;
-  Ok(())
+#[derive(Clone, Copy, ValueEnum)]
+enum CredentialOperation {
+  Get,
+  Store,
+  Erase,
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
-  env_logger::init();
+#[derive(Clone, Copy, ValueEnum)]
+enum DockerCredentialOperation {
+  Get,
+  Store,
+  Erase,
+  List,
+}
 
-  let cli = Cli::parse();
-  let options = options_from_cli(&cli)?;
+#[derive(Subcommand)]
+enum TagCommands {
+  /// Add a tag to an entry
+  Add {
+    /// Name of entry
+    name: String,
 
-  debug!("options {:?}", options);
+    /// Tag to add
+    tag: String,
+  },
 
-  match &cli.command {
-    Some(Commands::Create { path }) => command_create(path).await,
-    Some(Commands::List { output }) => {
-      command_list(&options, output.as_deref().unwrap_or("text")).await
-    }
-    Some(Commands::Get {
-      name,
-      field,
-      file,
+  /// Remove a tag from an entry
+  #[command(alias = "remove")]
+  Rm {
+    /// Name of entry
+    name: String,
+
+    /// Tag to remove
+    tag: String,
+  },
+}
+
+#[derive(Subcommand)]
+enum HistoryCommands {
+  /// Restore a previous version of an entry
+  Restore {
+    /// Name of entry
+    name: String,
+
+    /// Version to restore, as listed by `key history`
+    index: usize,
+  },
+}
+
+#[derive(Subcommand)]
+enum TrashCommands {
+  /// List the entries in the recycle bin
+  List,
+
+  /// Move an entry or group from the recycle bin back to where it was deleted from
+  Restore {
+    /// Name of entry or group in the recycle bin
+    name: String,
+  },
+
+  /// Permanently delete everything in the recycle bin
+  Empty,
+}
+
+#[derive(Subcommand)]
+enum GroupCommands {
+  /// List all groups
+  List,
+
+  /// Create a group, including missing parent groups
+  Create {
+    /// Path of the group ("Team/Prod")
+    path: String,
+  },
+
+  /// Rename a group
+  Rename {
+    /// Path of the group
+    path: String,
+
+    /// New name of the group
+    new_name: String,
+  },
+
+  /// Move a group to the recycle bin
+  Delete {
+    /// Path of the group
+    path: String,
+
+    /// Also delete a group that is not empty
+    #[arg(long)]
+    recursive: bool,
+
+    /// Delete the group for good instead of moving it to the recycle bin
+    #[arg(long)]
+    permanent: bool,
+  },
+
+  /// Move a group into another group
+  Move {
+    /// Path of the group
+    path: String,
+
+    /// Path of the new parent group ("/" for the root group)
+    parent: String,
+  },
+}
+
+/// A database to work with, named after its profile or file.
+struct Vault {
+  name: String,
+  options: KeeOptions,
+}
+
+/// The vaults selected with --kdbx and --profile. A single url and profile
+/// select one vault, where flags take precedence over environment variables,
+/// which take precedence over the profile. A url given as flag or in
+/// KEY_DATABASE_URL does not use the keyfile and S3 credentials of the profile,
+/// and a profile selected with --profile or KEY_PROFILE keeps its own url.
+/// Otherwise every url and profile is a vault of its own.
+fn vaults_from_cli(cli: &Cli) -> Result<Vec<Vault>> {
+  let config = Config::load()?;
+  if cli.kdbx.len() > 1 || cli.profile.len() > 1 {
+    return cli
+      .profile
+      .iter()
+      .chain(cli.kdbx.iter())
+      .map(|vault| vault_from_cli(cli, &config, vault))
+      .collect();
+  }
+
+  let profile_name = cli.profile.first().or(config.default_profile.as_ref());
+  let profile = config.profile(profile_name.map(String::as_str))?;
+  let profile_url = profile.url.clone();
+  let env = Profile::from_env();
+  let url = match (cli.kdbx.first(), cli.profile.first(), &env.url) {
+    (Some(url), _, _) => Some(url.clone()),
+    (None, Some(name), Some(env_url)) => {
+      if profile_url.as_ref().is_some_and(|url| url != env_url) {
+        eprintln!(
+          "{} KEY_DATABASE_URL is ignored, profile \"{}\" is selected",
+          "warning:".yellow(),
+          name
+        );
+      }
+      None
+    }
+    (None, None, env_url) => env_url.clone(),
+    (None, Some(_), None) => None,
+  };
+  let profile = match url {
+    Some(_) => profile.without_key(),
+    None => profile,
+  };
+  let settings = Profile {
+    url,
+    ..cli_settings(cli)
+  }
+  .or(Profile { url: None, ..env })
+  .or(profile);
+  let options = KeeOptions::new(settings, cli_password(cli))?;
+  // named after the profile, unless its url has been overridden
+  let name = match (profile_name, profile_url) {
+    (Some(name), Some(url)) if url == options.keepassdb => name.clone(),
+    _ => vault_name(&options.keepassdb),
+  };
+  Ok(vec![Vault { name, options }])
+}
+
+/// One of several vaults, given by profile name or database url. Profiles only
+/// use their own keyfile and S3 credentials, and the password is asked for. The
+/// key from flags and environment is only used when there is a single url, the
+/// other settings of urls come from the default profile.
+fn vault_from_cli(cli: &Cli, config: &Config, vault: &str) -> Result<Vault> {
+  if !vault.contains("://") {
+    return Ok(Vault {
+      name: vault.to_string(),
+      options: KeeOptions::new(config.profile(Some(vault))?, None)?,
+    });
+  }
+
+  let defaults = config.profile(None)?.without_key();
+  let settings = Profile {
+    url: Some(vault.to_string()),
+    ..Default::default()
+  };
+  let (settings, password) = match cli.kdbx.len() {
+    1 => (
+      settings
+        .or(cli_settings(cli))
+        .or(Profile::from_env())
+        .or(defaults),
+      cli_password(cli),
+    ),
+    _ => (settings.or(defaults), None),
+  };
+  Ok(Vault {
+    name: vault_name(vault),
+    options: KeeOptions::new(settings, password)?,
+  })
+}
+
+/// Settings given as flags, except the url.
+fn cli_settings(cli: &Cli) -> Profile {
+  Profile {
+    keyfile: cli.keyfile.clone(),
+    s3_access_key: cli.s3_access_key.clone(),
+    s3_secret_key: cli.s3_secret_key.clone(),
+    ..Default::default()
+  }
+}
+
+fn cli_password(cli: &Cli) -> Option<String> {
+  cli.password.clone().or(env::var("KEY_PASSWORD").ok())
+}
+
+/// File name of a database url, "team.kdbx" for "s3://host/bucket/team.kdbx".
+fn vault_name(url: &str) -> String {
+  url.rsplit('/').next().unwrap_or(url).to_string()
+}
+
+/// Opens every vault, asking for passwords one after another.
+async fn open_vaults(vaults: &[Vault]) -> Result<Vec<(&str, Database)>> {
+  let mut dbs = Vec::new();
+  for vault in vaults {
+    let db = get_database(&vault.options, &get_database_key(&vault.options)?).await?;
+    dbs.push((vault.name.as_str(), db));
+  }
+  Ok(dbs)
+}
+
+fn read_password(title: String) -> String {
+  let t = Input::new(title).placeholder("Password").password(true);
+  t.run().expect("error running input")
+}
+
+/// Asks for a password that protects a new database twice, so a typo does not
+/// lock it for good.
+fn read_new_password(title: String) -> Result<String> {
+  let password = read_password(title);
+  match read_password("Repeat the password".to_string()) == password {
+    true => Ok(password),
+    false => Err(anyhow!("The passwords do not match")),
+  }
+}
+
+/// Resolves the value for `set` without it having to appear in argv.
+/// Stdin and file contents are taken verbatim, so multi-line values survive.
+fn read_value(
+  value: &Option<String>,
+  prompt: &bool,
+  from_file: &Option<String>,
+  field: &str,
+) -> Result<String> {
+  if *prompt {
+    return Ok(read_password(format!("Value for {}", field)));
+  }
+
+  if let Some(path) = from_file {
+    return Ok(fs::read_to_string(path)?);
+  }
+
+  match value.as_deref() {
+    Some("-") => {
+      let mut buf = String::new();
+      io::stdin().read_to_string(&mut buf)?;
+      Ok(buf)
+    }
+    Some(value) => Ok(value.to_string()),
+    None => Err(anyhow::format_err!("No value provided.")),
+  }
+}
+
+fn get_database_key(options: &KeeOptions) -> Result<DatabaseKey> {
+  let dburl_parsed = Url::parse(options.keepassdb.as_str())?;
+  let name = dburl_parsed
+    .path()
+    .split('/')
+    .next_back()
+    .unwrap()
+    .to_string();
+
+  let mut key = DatabaseKey::new();
+
+  let keypath = &options.keepassdb_keyfile;
+  if let Some(keypath) = keypath {
+    key = key.with_keyfile(&mut File::open(keypath)?)?;
+  }
+
+  let password = &options.keepassdb_password;
+  if let Some(password) = password {
+    key = key.with_password(password.as_str())
+  } else {
+    key = key.with_password(read_password(format!("Password for {}", name)).as_str());
+  }
+
+  Ok(key)
+}
+
+/// Prints entries with the name of their vault, if they come from several.
+fn print_entries(
+  entries: Vec<(Option<&str>, String, &Entry)>,
+  format: Format,
+) -> Result<()> {
+  match format {
+    Format::Text => {
+      for (vault, path, e) in entries.iter() {
+        let path = match vault {
+          Some(vault) => format!("{} {}", format!("[{}]", vault).bright_black(), path),
+          None => path.clone(),
+        };
+        match entry_expiry(e) {
+          Some(_) if is_expired(e) => println!("{}  {}", path, "expired".red()),
+          Some(expiry) => println!(
+            "{}  {}",
+            path,
+            format!("expires {}", format_time(&Some(expiry))).bright_black()
+          ),
+          None => println!("{}", path),
+        }
+      }
+    }
+    _ => {
+      let entries: Vec<KeyEntry> = entries
+        .iter()
+        .map(|(vault, path, e)| KeyEntry::new(path, e).in_vault(*vault))
+        .collect();
+      println!("{}", render(format, &entries)?);
+    }
+  }
+  Ok(())
+}
+
+async fn command_list(
+  vaults: &[Vault],
+  format: Format,
+  legacy: bool,
+  tags: &[String],
+) -> Result<()> {
+  let mut dbs = open_vaults(vaults).await?;
+
+  if legacy {
+    if dbs.len() > 1 {
+      return Err(anyhow!("The legacy json tree can only list one vault"));
+    }
+    println!("{}", to_json(dbs.remove(0).1)?);
+    return Ok(());
+  }
+
+  let tagged = dbs.len() > 1;
+  let entries = dbs
+    .iter()
+    .flat_map(|(vault, db)| {
+      list_entries(db)
+        .into_iter()
+        .filter(|(_, e)| tags.iter().all(|tag| has_tag(e, tag)))
+        .map(move |(path, e)| (tagged.then_some(*vault), path, e))
+    })
+    .collect();
+  print_entries(entries, format)
+}
+
+async fn command_search(vaults: &[Vault], query: &str, format: Format) -> Result<()> {
+  let dbs = open_vaults(vaults).await?;
+  let tagged = dbs.len() > 1;
+  let entries = dbs
+    .iter()
+    .flat_map(|(vault, db)| {
+      search_entries(db, query)
+        .into_iter()
+        .map(move |(path, e)| (tagged.then_some(*vault), path, e))
+    })
+    .collect();
+  print_entries(entries, format)
+}
+
+async fn command_cp(
+  cli: &Cli,
+  mut vaults: Vec<Vault>,
+  name: &str,
+  new_name: &Option<String>,
+  from: &Option<String>,
+  to: &str,
+) -> Result<()> {
+  let config = Config::load()?;
+  let from = match from {
+    Some(from) => vault_from_cli(cli, &config, from)?,
+    None if vaults.len() == 1 => vaults.remove(0),
+    None => {
+      return Err(anyhow!(
+        "Several vaults are selected, choose one with --from"
+      ))
+    }
+  };
+  let to = vault_from_cli(cli, &config, to)?;
+  if from.options.keepassdb == to.options.keepassdb {
+    return Err(anyhow!("Source and target are the same database"));
+  }
+
+  let source = get_database(&from.options, &get_database_key(&from.options)?).await?;
+  let key = get_database_key(&to.options)?;
+  let mut target = get_database(&to.options, &key).await?;
+
+  let path = match new_name {
+    Some(new_name) => new_name.clone(),
+    // also resolves entries given by uuid to their path
+    None => {
+      let uuid = find_entry(&source, name)?.uuid;
+      walk_entries(&source.root)
+        .into_iter()
+        .find(|(_, e)| e.uuid == uuid)
+        .map(|(path, _)| path)
+        .expect("Entry should exist")
+    }
+  };
+  copy_entry(&source, name, &mut target, &path)?;
+  write_database(&to.options, &mut target, &key).await?;
+  println!(
+    "Copied {} from {} to {} as {}",
+    name, from.name, to.name, path
+  );
+  Ok(())
+}
+
+struct ChooseEntry {
+  value: String,
+  user: Option<String>,
+  tags: Vec<String>,
+  expired: bool,
+}
+
+impl fmt::Display for ChooseEntry {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(
+      f,
+      "{} ({})",
+      self.value,
+      self.user.clone().unwrap_or("".to_string()).bright_black()
+    )?;
+    for tag in self.tags.iter() {
+      write!(f, " {}", format!("#{}", tag).cyan())?;
+    }
+    if self.expired {
+      write!(f, " {}", "expired".red())?;
+    }
+    Ok(())
+  }
+}
+
+fn choose_key_ui(db: &Database, tags: &[String]) -> ChooseEntry {
+  let ms: Select<ChooseEntry> = Select::new("Keys")
+    .description("Select a key")
+    .filterable(true);
+
+  let options: Vec<DemandOption<ChooseEntry>> = list_entries(db)
+    .into_iter()
+    .filter(|(_, e)| tags.iter().all(|tag| has_tag(e, tag)))
+    .map(|(path, e)| {
+      DemandOption::new(ChooseEntry {
+        user: Some(e.get_username().unwrap_or("").to_string()),
+        tags: e.tags.clone(),
+        expired: is_expired(e),
+        value: path,
+      })
+    })
+    .collect();
+
+  ms.options(options).run().expect("error running select")
+}
+
+async fn command_choose(
+  options: &KeeOptions,
+  field: &str,
+  clipboard: &bool,
+  otp: &bool,
+  tags: &[String],
+) -> Result<()> {
+  let db = get_database(options, &get_database_key(options)?).await?;
+
+  let entry = if otp.to_owned() {
+    get_entry_otp(&db, &choose_key_ui(&db, tags).value, "otp")?
+  } else {
+    get_entry(&db, &choose_key_ui(&db, tags).value, field)?
+  };
+
+  if clipboard.to_owned() {
+    to_clipboard(options, entry)?;
+    println!("Copied to clipboard");
+    return Ok(());
+  }
+
+  println!("{}", entry);
+  Ok(())
+}
+
+async fn command_tui(options: &KeeOptions, lock_after: &Option<u64>) -> Result<()> {
+  let key = get_database_key(options)?;
+  let db = get_database(options, &key).await?;
+  let lock_after = lock_after
+    .or(options.lock_timeout)
+    .unwrap_or(DEFAULT_LOCK_TIMEOUT);
+  run_tui(options, db, key, lock_after, &|value| {
+    to_clipboard(options, value)
+  })
+  .await
+}
+
+async fn command_set_expiry(
+  options: &KeeOptions,
+  name: &str,
+  expiry: &str,
+) -> Result<()> {
+  let expiry = parse_expiry(expiry)?;
+  let key = get_database_key(options)?;
+  let mut db = get_database(options, &key).await?;
+  set_entry_expiry(&mut db, name, expiry)?;
+  debug!("Set expiry of {} to {:?}", name, expiry);
+  write_database(options, &mut db, &key).await?;
+  Ok(())
+}
+
+async fn command_expiring(
+  options: &KeeOptions,
+  within: &str,
+  format: Format,
+) -> Result<()> {
+  let db = get_database(options, &get_database_key(options)?).await?;
+  let entries = expiring_entries(&db, within)?;
+
+  match format {
+    Format::Text => {
+      for entry in entries.iter() {
+        let time = format_time(&Some(entry.expiry_time));
+        match entry.expired {
+          true => println!("{}  {}  {}", time.red(), entry.path, "expired".red()),
+          false => println!("{}  {}", time, entry.path),
+        }
+      }
+    }
+    _ => println!("{}", render(format, &entries)?),
+  }
+
+  let expired = entries.iter().filter(|e| e.expired).count();
+  if expired > 0 {
+    return Err(anyhow!("{} entries have expired", expired));
+  }
+  Ok(())
+}
+
+fn describe_issue(issue: &KeyAuditIssue) -> String {
+  match issue {
+    KeyAuditIssue::Weak { score } => format!("weak (score {}/4)", score),
+    KeyAuditIssue::Reused { with } => format!("reused in {}", with.join(", ")),
+    KeyAuditIssue::Old { days, .. } => format!("unchanged for {} days", days),
+    KeyAuditIssue::Breached { count } => format!("breached {} times", count),
+  }
+}
+
+async fn command_audit(
+  options: &KeeOptions,
+  audit: AuditOptions,
+  format: Format,
+) -> Result<()> {
+  let db = get_database(options, &get_database_key(options)?).await?;
+  let items = audit_entries(&db, &audit)?;
+
+  match format {
+    Format::Text => {
+      let width = items.iter().map(|i| i.path.len()).max().unwrap_or(0);
+      for item in items.iter() {
+        let issues: Vec<String> = item.issues.iter().map(describe_issue).collect();
+        println!("{:<width$}  {}", item.path, issues.join(", ").red());
+      }
+    }
+    _ => println!("{}", render(format, &items)?),
+  }
+
+  if !items.is_empty() {
+    return Err(anyhow!("{} entries have password issues", items.len()));
+  }
+  Ok(())
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn command_import(
+  options: &KeeOptions,
+  file: &str,
+  from: ImportFormat,
+  group: &str,
+  dry_run: &bool,
+  skip_attachments: &bool,
+  source_keyfile: &Option<String>,
+  format: Format,
+) -> Result<()> {
+  let data = fs::read(file)?;
+  let source_key = match from {
+    ImportFormat::Kdbx => {
+      let password = env::var("KEY_SOURCE_PASSWORD")
+        .unwrap_or_else(|_| read_password(format!("Password for {}", file)));
+      let keyfile = source_keyfile.as_ref().map(fs::read).transpose()?;
+      Some(key_from(Some(password), keyfile)?)
+    }
+    _ => None,
+  };
+  let entries = read_import(from, data, source_key)?;
+
+  let key = get_database_key(options)?;
+  let mut db = get_database(options, &key).await?;
+  let items = import_entries(&mut db, entries, group, *dry_run, *skip_attachments)?;
+  if !dry_run {
+    write_database(options, &mut db, &key).await?;
+  }
+
+  if format != Format::Text {
+    println!("{}", render(format, &items)?);
+    return Ok(());
+  }
+
+  for item in items.iter() {
+    match item.status {
+      ImportStatus::Imported => println!("{} {}", "+".green(), item.path),
+      ImportStatus::Exists => {
+        println!(
+          "{}",
+          format!("= {} (exists, skipped)", item.path).bright_black()
+        )
+      }
+    }
+    for attachment in item.skipped_attachments.iter() {
+      println!(
+        "  {}",
+        format!("attachment \"{}\" was not imported", attachment).yellow()
+      );
+    }
+  }
+  let count = items
+    .iter()
+    .filter(|i| i.status == ImportStatus::Imported)
+    .count();
+  match dry_run {
+    true => println!("Would import {} entries", count),
+    false => println!("Imported {} entries", count),
+  }
+  Ok(())
+}
+
+async fn command_export(
+  options: &KeeOptions,
+  out: &str,
+  to: ExportFormat,
+  export: ExportOptions,
+  new_password: &bool,
+  new_keyfile: &Option<String>,
+) -> Result<()> {
+  let key = get_database_key(options)?;
+  let db = get_database(options, &key).await?;
+
+  let export_key = match to {
+    ExportFormat::Kdbx if *new_password || new_keyfile.is_some() => {
+      let password = new_password
+        .then(|| {
+          env::var("KEY_EXPORT_PASSWORD")
+            .or_else(|_| read_new_password(format!("New password for {}", out)))
+        })
+        .transpose()?;
+      let keyfile = new_keyfile.as_ref().map(fs::read).transpose()?;
+      Some(key_from(password, keyfile)?)
+    }
+    ExportFormat::Kdbx => Some(key),
+    _ if *new_password || new_keyfile.is_some() => {
+      return Err(anyhow!("A new key can only be set for kdbx exports"));
+    }
+    _ => None,
+  };
+  let exported = export_entries(&db, to, &export, export_key)?;
+
+  match out {
+    "-" => io::stdout().write_all(&exported.data)?,
+    _ => {
+      write_private(out, &exported.data)?;
+      println!("Exported {} entries to {}", exported.entries, out);
+    }
+  }
+  Ok(())
+}
+
+async fn command_extract(
+  options: &KeeOptions,
+  group: &str,
+  to: &str,
+  password: &bool,
+  keyfile: &Option<String>,
+  force: &bool,
+) -> Result<()> {
+  let db = get_database(options, &get_database_key(options)?).await?;
+  let mut extracted = extract_group(&db, group)?;
+
+  let target = KeeOptions {
+    keepassdb: to.to_string(),
+    keepassdb_keyfile: None,
+    keepassdb_password: None,
+    s3_access_key: options.s3_access_key.clone(),
+    s3_secret_key: options.s3_secret_key.clone(),
+    ..Default::default()
+  };
+  if !force && database_exists(&target).await? {
+    return Err(anyhow!(
+      "{} already exists, use --force to overwrite it",
+      to
+    ));
+  }
+
+  let password = (*password || keyfile.is_none())
+    .then(|| {
+      env::var("KEY_EXTRACT_PASSWORD")
+        .or_else(|_| read_new_password(format!("Password for {}", to)))
+    })
+    .transpose()?;
+  let keyfile = keyfile.as_ref().map(fs::read).transpose()?;
+  let key = key_from(password, keyfile)?;
+  write_database(&target, &mut extracted, &key).await?;
+
+  let count = walk_entries(&extracted.root).len();
+  println!("Extracted {} entries to {}", count, to);
+  Ok(())
+}
+
+/// Name of the variable an entry title becomes with --env-group: "api key"
+/// becomes "API_KEY".
+fn env_var_name(title: &str) -> String {
+  title
+    .chars()
+    .map(|c| match c.is_ascii_alphanumeric() {
+      true => c.to_ascii_uppercase(),
+      false => '_',
+    })
+    .collect()
+}
+
+/// Resolves the --env and --env-group options of `run` into variables. Values
+/// never end up in error messages.
+fn resolve_env(
+  db: &Database,
+  mappings: &[String],
+  groups: &[String],
+) -> Result<Vec<(String, String)>> {
+  let mut vars = Vec::new();
+
+  for group in groups {
+    for e in get_group(db, group)?.entries.iter() {
+      let title = e.get_title().unwrap_or_default();
+      let password = e.get_password().unwrap_or_default();
+      vars.push((env_var_name(title), password.to_string()));
+    }
+  }
+
+  // explicit mappings win over groups
+  for mapping in mappings {
+    let (name, reference) = mapping
+      .split_once('=')
+      .filter(|(name, _)| !name.is_empty())
+      .ok_or(anyhow!(
+        "Invalid --env \"{}\", use NAME=ENTRY:FIELD",
+        mapping
+      ))?;
+    let reference = match reference.starts_with(REFERENCE_SCHEME) {
+      true => KeyReference::parse(reference)?,
+      false => env_reference(reference),
+    };
+    vars.push((name.to_string(), reference.resolve(db)?));
+  }
+
+  Ok(vars)
+}
+
+/// Reads the ENTRY:FIELD form of --env. The field defaults to Password and
+/// "otp" is the current code.
+fn env_reference(reference: &str) -> KeyReference {
+  // "uuid:<uuid>" has a colon of its own
+  let (prefix, rest) = match reference.strip_prefix(UUID_PREFIX) {
+    Some(rest) => (UUID_PREFIX, rest),
+    None => ("", reference),
+  };
+  let (entry, field) = match rest.rsplit_once(':') {
+    Some((entry, field)) => (format!("{}{}", prefix, entry), field),
+    None => (reference.to_string(), "Password"),
+  };
+  let target = match field {
+    "otp" => ReferenceTarget::Otp,
+    _ => ReferenceTarget::Field(field.to_string()),
+  };
+  KeyReference { entry, target }
+}
+
+async fn command_run(
+  options: &KeeOptions,
+  env: &[String],
+  env_group: &[String],
+  command: &[String],
+) -> Result<()> {
+  let db = get_database(options, &get_database_key(options)?).await?;
+  let vars = resolve_env(&db, env, env_group)?;
+  drop(db);
+
+  let mut child = tokio::process::Command::new(&command[0])
+    .args(&command[1..])
+    .envs(vars)
+    .spawn()
+    .map_err(|err| anyhow!("Can not run {}: {}", command[0], err))?;
+  let status = wait_forwarding_signals(&mut child).await?;
+  process::exit(exit_code(status));
+}
+
+/// Waits for the child while passing on the signals we receive, so it can shut
+/// down on its own terms. Ctrl-C and Ctrl-\ on the terminal already reach the
+/// child, which is in our process group, so those are not sent a second time.
+#[cfg(unix)]
+async fn wait_forwarding_signals(
+  child: &mut tokio::process::Child,
+) -> Result<ExitStatus> {
+  use tokio::signal::unix::{signal, SignalKind};
+
+  let pid = child.id().ok_or(anyhow!("Child process has no pid"))? as libc::pid_t;
+  let listen = |sig| signal(SignalKind::from_raw(sig));
+  let mut interrupt = listen(libc::SIGINT)?;
+  let mut terminate = listen(libc::SIGTERM)?;
+  let mut hangup = listen(libc::SIGHUP)?;
+  let mut quit = listen(libc::SIGQUIT)?;
+  let mut user1 = listen(libc::SIGUSR1)?;
+  let mut user2 = listen(libc::SIGUSR2)?;
+
+  loop {
+    let sig = tokio::select! {
+      status = child.wait() => return Ok(status?),
+      _ = interrupt.recv() => libc::SIGINT,
+      _ = terminate.recv() => libc::SIGTERM,
+      _ = hangup.recv() => libc::SIGHUP,
+      _ = quit.recv() => libc::SIGQUIT,
+      _ = user1.recv() => libc::SIGUSR1,
+      _ = user2.recv() => libc::SIGUSR2,
+    };
+    if (sig == libc::SIGINT || sig == libc::SIGQUIT) && in_terminal_foreground() {
+      continue;
+    }
+    // SAFETY: kill only sends a signal, `pid` is our child that was not reaped yet
+    unsafe { libc::kill(pid, sig) };
+  }
+}
+
+/// Whether our process group is the foreground group of the controlling
+/// terminal, which then sends its keyboard signals to all of it.
+#[cfg(unix)]
+fn in_terminal_foreground() -> bool {
+  use std::os::fd::AsRawFd;
+
+  match File::open("/dev/tty") {
+    // SAFETY: both only read the process group ids
+    Ok(tty) => unsafe { libc::tcgetpgrp(tty.as_raw_fd()) == libc::getpgrp() },
+    Err(_) => false,
+  }
+}
+
+/// Ctrl-C reaches every process on the console, so the child gets it already.
+#[cfg(not(unix))]
+async fn wait_forwarding_signals(
+  child: &mut tokio::process::Child,
+) -> Result<ExitStatus> {
+  loop {
+    tokio::select! {
+      status = child.wait() => return Ok(status?),
+      _ = tokio::signal::ctrl_c() => {}
+    }
+  }
+}
+
+/// Exit code to pass on, 128 + the signal number if the child was killed, like
+/// shells do.
+fn exit_code(status: ExitStatus) -> i32 {
+  #[cfg(unix)]
+  {
+    use std::os::unix::process::ExitStatusExt;
+    if let Some(sig) = status.signal() {
+      return 128 + sig;
+    }
+  }
+  status.code().unwrap_or(1)
+}
+
+async fn command_read(
+  options: &KeeOptions,
+  uri: &str,
+  clipboard: &bool,
+  format: Format,
+) -> Result<()> {
+  let reference = KeyReference::parse(uri)?;
+  let db = get_database(options, &get_database_key(options)?).await?;
+  let value = reference.resolve(&db)?;
+
+  if *clipboard {
+    to_clipboard(options, value)?;
+    println!("Copied {} to clipboard", reference);
+    return Ok(());
+  }
+
+  match format {
+    Format::Text => println!("{}", value),
+    _ => {
+      let data =
+        serde_json::json!({ "reference": reference.to_string(), "value": value });
+      println!("{}", render(format, &data)?);
+    }
+  }
+  Ok(())
+}
+
+async fn command_inject(
+  options: &KeeOptions,
+  input: &Option<String>,
+  output: &Option<String>,
+  check: &bool,
+) -> Result<()> {
+  // ask for the password before stdin is taken by the template
+  let key = get_database_key(options)?;
+  let template = match input.as_deref() {
+    None | Some("-") => {
+      let mut buf = String::new();
+      io::stdin().read_to_string(&mut buf)?;
+      buf
+    }
+    Some(path) => fs::read_to_string(path)?,
+  };
+  let db = get_database(options, &key).await?;
+
+  if *check {
+    let count = check_template(&db, &template)?;
+    println!("All {} references resolve", count);
+    return Ok(());
+  }
+
+  let rendered = render_template(&db, &template)?;
+  match output {
+    Some(path) => write_private(path, rendered.as_bytes())?,
+    None => io::stdout().write_all(rendered.as_bytes())?,
+  }
+  Ok(())
+}
+
+/// Writes a file only the owner can read. Existing files are restricted too,
+/// before anything is written to them.
+fn write_private(path: &str, contents: &[u8]) -> Result<()> {
+  let mut options = fs::OpenOptions::new();
+  options.write(true).create(true).truncate(true);
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::OpenOptionsExt;
+    options.mode(0o600);
+  }
+  let mut file = options.open(path)?;
+  #[cfg(unix)]
+  {
+    use std::os::unix::fs::PermissionsExt;
+    file.set_permissions(fs::Permissions::from_mode(0o600))?;
+  }
+  file.write_all(contents)?;
+  Ok(())
+}
+
+async fn command_git_credential(
+  options: &KeeOptions,
+  operation: CredentialOperation,
+  group: &str,
+) -> Result<()> {
+  // ask for the password before git's request is read from stdin
+  let key = get_database_key(options)?;
+  let mut input = String::new();
+  io::stdin().read_to_string(&mut input)?;
+  let credential = GitCredential::parse(&input)?;
+
+  let mut db = get_database(options, &key).await?;
+  let changed = match operation {
+    CredentialOperation::Get => {
+      print!("{}", get_git_credential(&db, &credential).to_reply());
+      false
+    }
+    CredentialOperation::Store => store_git_credential(&mut db, &credential, group)?,
+    CredentialOperation::Erase => erase_git_credential(&mut db, &credential)?,
+  };
+
+  if changed {
+    write_database(options, &mut db, &key).await?;
+  }
+  Ok(())
+}
+
+async fn command_docker_credential(
+  options: &KeeOptions,
+  operation: DockerCredentialOperation,
+  group: &str,
+) -> Result<()> {
+  // ask for the password before the request is read from stdin
+  let key = get_database_key(options)?;
+  let mut input = String::new();
+  if !matches!(operation, DockerCredentialOperation::List) {
+    io::stdin().read_to_string(&mut input)?;
+  }
+
+  let mut db = get_database(options, &key).await?;
+  let changed = match operation {
+    DockerCredentialOperation::Get => match get_docker_credential(&db, group, &input) {
+      Some(credential) => {
+        println!("{}", serde_json::to_string(&credential)?);
+        false
+      }
+      None => return Err(anyhow!(DOCKER_CREDENTIALS_NOT_FOUND)),
+    },
+    DockerCredentialOperation::Store => {
+      let credential: DockerCredential = serde_json::from_str(&input)?;
+      store_docker_credential(&mut db, group, &credential)?
+    }
+    DockerCredentialOperation::Erase => erase_docker_credential(&mut db, group, &input)?,
+    DockerCredentialOperation::List => {
+      println!(
+        "{}",
+        serde_json::to_string(&list_docker_credentials(&db, group))?
+      );
+      false
+    }
+  };
+
+  if changed {
+    write_database(options, &mut db, &key).await?;
+  }
+  Ok(())
+}
+
+#[cfg(unix)]
+async fn command_ssh_add(
+  options: &KeeOptions,
+  name: &str,
+  field: &Option<String>,
+  lifetime: &str,
+) -> Result<()> {
+  use ssh_agent_lib::agent::Session;
+  use ssh_agent_lib::client::Client;
+  use ssh_agent_lib::proto::{
+    AddIdentity, AddIdentityConstrained, KeyConstraint, PrivateCredential,
+  };
+
+  let lifetime = parse_lifetime(lifetime)?;
+  let socket = env::var("SSH_AUTH_SOCK")
+    .map_err(|_| anyhow!("SSH_AUTH_SOCK is not set, is an ssh agent running?"))?;
+  let db = get_database(options, &get_database_key(options)?).await?;
+  let key = entry_ssh_key(find_entry(&db, name)?, field.as_deref())?;
+  drop(db);
+
+  let stream = tokio::net::UnixStream::connect(&socket)
+    .await
+    .map_err(|err| anyhow!("Can not connect to the ssh agent at {}: {}", socket, err))?;
+  let mut agent = Client::new(stream);
+  let identity = AddIdentity {
+    credential: PrivateCredential::Key {
+      privkey: key.key_data().clone(),
+      comment: key.comment().to_string(),
+    },
+  };
+  match lifetime {
+    0 => agent.add_identity(identity).await?,
+    _ => {
+      agent
+        .add_identity_constrained(AddIdentityConstrained {
+          identity,
+          constraints: vec![KeyConstraint::Lifetime(lifetime)],
+        })
+        .await?
+    }
+  }
+
+  let fingerprint = key.fingerprint(Default::default());
+  match lifetime {
+    0 => println!("Identity added: {} ({})", key.comment(), fingerprint),
+    _ => println!(
+      "Identity added: {} ({}), lifetime {}s",
+      key.comment(),
+      fingerprint,
+      lifetime
+    ),
+  }
+  Ok(())
+}
+
+#[cfg(not(unix))]
+async fn command_ssh_add(
+  _options: &KeeOptions,
+  _name: &str,
+  _field: &Option<String>,
+  _lifetime: &str,
+) -> Result<()> {
+  Err(anyhow!("ssh-add is only supported on unix"))
+}
+
+/// Listens until interrupted and removes the socket afterwards. The keys are
+/// read once, the database is not kept open.
+#[cfg(unix)]
+async fn command_ssh_agent(
+  options: &KeeOptions,
+  names: &[String],
+  socket: &Option<PathBuf>,
+) -> Result<()> {
+  let db = get_database(options, &get_database_key(options)?).await?;
+  let keys = ssh_keys(&db, names)?;
+  drop(db);
+  if keys.is_empty() {
+    return Err(anyhow!("No entries with ssh keys found"));
+  }
+
+  let socket = socket
+    .clone()
+    .unwrap_or_else(|| env::temp_dir().join(format!("key-agent.{}.sock", process::id())));
+  let listener = tokio::net::UnixListener::bind(&socket)
+    .map_err(|err| anyhow!("Can not listen on {}: {}", socket.display(), err))?;
+
+  println!(
+    "SSH_AUTH_SOCK={}; export SSH_AUTH_SOCK;",
+    socket.to_string_lossy()
+  );
+  println!("SSH_AGENT_PID={}; export SSH_AGENT_PID;", process::id());
+  eprintln!("Serving {} ssh keys", keys.len());
+  io::stdout().flush()?;
+
+  let result = tokio::select! {
+    result = ssh_agent_lib::agent::listen(listener, KeyAgent::new(keys)) => {
+      result.map_err(anyhow::Error::from)
+    }
+    result = shutdown_signal() => result,
+  };
+  fs::remove_file(&socket)?;
+  result
+}
+
+#[cfg(not(unix))]
+async fn command_ssh_agent(
+  _options: &KeeOptions,
+  _names: &[String],
+  _socket: &Option<PathBuf>,
+) -> Result<()> {
+  Err(anyhow!("ssh-agent is only supported on unix"))
+}
+
+/// Resolves on SIGINT or SIGTERM, the way `ssh-agent -k` stops an agent.
+#[cfg(unix)]
+async fn shutdown_signal() -> Result<()> {
+  use tokio::signal::unix::{signal, SignalKind};
+
+  let mut interrupt = signal(SignalKind::interrupt())?;
+  let mut terminate = signal(SignalKind::terminate())?;
+  tokio::select! {
+    _ = interrupt.recv() => {}
+    _ = terminate.recv() => {}
+  }
+  Ok(())
+}
+
+async fn command_tag(options: &KeeOptions, command: &TagCommands) -> Result<()> {
+  let key = get_database_key(options)?;
+  let mut db = get_database(options, &key).await?;
+
+  match command {
+    TagCommands::Add { name, tag } => add_tag(&mut db, name, tag)?,
+    TagCommands::Rm { name, tag } => remove_tag(&mut db, name, tag)?,
+  }
+
+  write_database(options, &mut db, &key).await?;
+  Ok(())
+}
+
+/// Copies the value to the clipboard. With a clipboard timeout, a background
+/// process clears it again, unless something else has been copied since.
+fn to_clipboard(options: &KeeOptions, entry: String) -> Result<()> {
+  let mut ctx = ClipboardContext::new()
+    .map_err(|err| anyhow!("Could not access the clipboard: {}", err))?;
+  ctx
+    .set_contents(entry.clone())
+    .map_err(|err| anyhow!("Could not access the clipboard: {}", err))?;
+
+  if let Some(timeout) = options.clipboard_timeout {
+    let mut child = process::Command::new(env::current_exe()?)
+      .args(["clear-clipboard", "--after", &timeout.to_string()])
+      .stdin(process::Stdio::piped())
+      .stdout(process::Stdio::null())
+      .stderr(process::Stdio::null())
+      .spawn()?;
+    // the hash, not the value, so it does not show up in the process list
+    let mut stdin = child
+      .stdin
+      .take()
+      .ok_or(anyhow!("No stdin to clear-clipboard"))?;
+    stdin.write_all(clipboard_hash(&entry).as_bytes())?;
+  }
+  Ok(())
+}
+
+fn clipboard_hash(value: &str) -> String {
+  Sha1::digest(value.as_bytes())
+    .iter()
+    .map(|b| format!("{:02x}", b))
+    .collect()
+}
+
+/// Clears the clipboard after `after` seconds if it still holds the value whose
+/// hash is read from stdin.
+async fn command_clear_clipboard(after: u64) -> Result<()> {
+  let mut hash = String::new();
+  io::stdin().read_to_string(&mut hash)?;
+  tokio::time::sleep(std::time::Duration::from_secs(after)).await;
+
+  let mut ctx = ClipboardContext::new().unwrap();
+  if let Ok(contents) = ctx.get_contents() {
+    if clipboard_hash(&contents) == hash.trim() {
+      ctx.set_contents(String::new()).unwrap();
+    }
+  }
+  Ok(())
+}
+
+async fn command_get(
+  options: &KeeOptions,
+  name: &str,
+  field: &str,
+  clipboard: &bool,
+  format: Format,
+) -> Result<()> {
+  let db = get_database(options, &get_database_key(options)?).await?;
+  let entry = get_entry(&db, name, field)?;
+
+  if clipboard.to_owned() {
+    to_clipboard(options, entry)?;
+    println!("Copied {field} to clipboard");
+    return Ok(());
+  }
+
+  match format {
+    Format::Text => println!("{}", entry),
+    _ => {
+      let detail = get_entry_detail(&db, name, true)?;
+      println!("{}", render(format, &detail)?);
+    }
+  }
+  Ok(())
+}
+
+fn format_time(time: &Option<NaiveDateTime>) -> String {
+  time
+    .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+    .unwrap_or("-".to_string())
+}
+
+fn print_entry_detail(detail: &KeyEntryDetail) {
+  let row = |label: &str, value: &str| println!("{:<10} {}", label.bold(), value);
+
+  row("Path", &detail.path);
+  row("UUID", &detail.uuid);
+
+  for (name, field) in detail.fields.iter() {
+    if name == "Notes" {
+      continue;
+    }
+    match &field.value {
+      Some(value) => row(name, value),
+      None => row(name, &"********".bright_black().to_string()),
+    }
+  }
+
+  if !detail.tags.is_empty() {
+    row("Tags", &detail.tags.join(", "));
+  }
+  if let Some(notes) = &detail.notes {
+    row("Notes", notes);
+  }
+  if let Some(icon_id) = detail.icon_id {
+    row("Icon", &icon_id.to_string());
+  }
+  row(
+    "Expires",
+    &match detail.expiry_time {
+      Some(expiry) if expiry <= Times::now() => {
+        format!("{} {}", format_time(&Some(expiry)), "expired".red())
+      }
+      Some(expiry) => format_time(&Some(expiry)),
+      None => "never".to_string(),
+    },
+  );
+  row("Created", &format_time(&detail.created));
+  row("Modified", &format_time(&detail.modified));
+  row("Accessed", &format_time(&detail.accessed));
+  row("History", &format!("{} items", detail.history_items));
+}
+
+async fn command_show(
+  options: &KeeOptions,
+  name: &str,
+  reveal: &bool,
+  format: Format,
+) -> Result<()> {
+  let db = get_database(options, &get_database_key(options)?).await?;
+  let detail = get_entry_detail(&db, name, *reveal)?;
+
+  match format {
+    Format::Text => print_entry_detail(&detail),
+    _ => println!("{}", render(format, &detail)?),
+  }
+  Ok(())
+}
+
+fn print_field_change(change: &KeyFieldChange) {
+  let value = |v: &Option<String>| match change.protected {
+    true => "********".to_string(),
+    false => v.clone().unwrap_or_default(),
+  };
+  let line = match change.kind {
+    ChangeKind::Added => format!("+ {}: {}", change.field, value(&change.new)).green(),
+    ChangeKind::Removed => format!("- {}: {}", change.field, value(&change.old)).red(),
+    ChangeKind::Changed => format!(
+      "~ {}: {} -> {}",
+      change.field,
+      value(&change.old),
+      value(&change.new)
+    )
+    .yellow(),
+  };
+  println!("    {}", line);
+}
+
+async fn command_history(
+  options: &KeeOptions,
+  name: &str,
+  diff: &bool,
+  format: Format,
+) -> Result<()> {
+  let db = get_database(options, &get_database_key(options)?).await?;
+  let history = get_entry_history(&db, name, *diff)?;
+
+  if format != Format::Text {
+    println!("{}", render(format, &history)?);
+    return Ok(());
+  }
+
+  if history.is_empty() {
+    println!("No history for {}", name);
+  }
+
+  for item in history.iter() {
+    println!(
+      "{:>3}  {}  {}",
+      item.index,
+      format_time(&item.modified),
+      item.title
+    );
+    for change in item.changes.iter().flatten() {
+      print_field_change(change);
+    }
+  }
+  Ok(())
+}
+
+async fn command_history_restore(
+  options: &KeeOptions,
+  name: &str,
+  index: &usize,
+) -> Result<()> {
+  let key = get_database_key(options)?;
+  let mut db = get_database(options, &key).await?;
+  restore_entry(&mut db, name, *index)?;
+  debug!("Restored version {} of {}", index, name);
+  write_database(options, &mut db, &key).await?;
+  Ok(())
+}
+
+async fn command_get_file(options: &KeeOptions, name: &str, field: &str) -> Result<()> {
+  let db = get_database(options, &get_database_key(options)?).await?;
+  io::stdout().write_all(get_entry_attachment(&db, name, field)?)?;
+  Ok(())
+}
+
+async fn command_otp(
+  options: &KeeOptions,
+  name: &str,
+  field: &str,
+  clipboard: &bool,
+  format: Format,
+) -> Result<()> {
+  let db = get_database(options, &get_database_key(options)?).await?;
+  let val = get_entry_otp_details(&db, name, field)?;
+
+  if clipboard.to_owned() {
+    to_clipboard(options, val.code)?;
+    println!("Copied {field} to clipboard");
+    return Ok(());
+  }
+
+  match format {
+    Format::Text => println!("{}", val.code),
+    _ => println!("{}", render(format, &val)?),
+  }
+  Ok(())
+}
+
+/// Takes the key from the caller, so a password prompt comes before the value
+/// is read from stdin.
+async fn command_set(
+  options: &KeeOptions,
+  key: DatabaseKey,
+  name: &str,
+  value: &str,
+  field: &str,
+) -> Result<()> {
+  let mut db = get_database(options, &key).await?;
+  set_entry(&mut db, name, value, field)?;
+  debug!("Set entry field {} of {}", field, name);
+  write_database(options, &mut db, &key).await?;
+  Ok(())
+}
+
+/// Generates with the given policy, the policy of the profile or the default.
+fn generate_from_policy(options: &KeeOptions, policy: &Option<String>) -> Result<String> {
+  match policy.as_ref().or(options.generator.as_ref()) {
+    Some(policy) => policy.parse::<PasswordPolicy>()?.generate(),
+    None => PasswordPolicy::default().generate(),
+  }
+}
+
+fn reveal_generated(
+  options: &KeeOptions,
+  value: String,
+  print: &bool,
+  clipboard: &bool,
+) -> Result<()> {
+  if *clipboard {
+    to_clipboard(options, value)?;
+    println!("Copied generated password to clipboard");
+  } else if *print {
+    println!("{}", value);
+  }
+  Ok(())
+}
+
+async fn command_new(
+  options: &KeeOptions,
+  name: &str,
+  user: &Option<String>,
+  url: &Option<String>,
+  generate: &Option<Option<String>>,
+  print: &bool,
+  clipboard: &bool,
+) -> Result<()> {
+  let key = get_database_key(options)?;
+  let mut db = get_database(options, &key).await?;
+  new_entry(&mut db, name, user.as_deref(), url.as_deref())?;
+
+  let password = match generate {
+    Some(policy) => {
+      let password = generate_from_policy(options, policy)?;
+      set_entry(&mut db, name, &password, "Password")?;
+      Some(password)
+    }
+    None => None,
+  };
+
+  debug!("Created entry {}", name);
+  write_database(options, &mut db, &key).await?;
+
+  if let Some(password) = password {
+    reveal_generated(options, password, print, clipboard)?;
+  }
+  Ok(())
+}
+
+async fn command_rename(options: &KeeOptions, name: &str, new_name: &str) -> Result<()> {
+  let key = get_database_key(options)?;
+  let mut db = get_database(options, &key).await?;
+  rename_entry(&mut db, name, new_name)?;
+  debug!("Set Title of field {} to {}", name, new_name);
+  write_database(options, &mut db, &key).await?;
+  Ok(())
+}
+
+async fn command_mv(options: &KeeOptions, name: &str, group: &str) -> Result<()> {
+  let key = get_database_key(options)?;
+  let mut db = get_database(options, &key).await?;
+  move_entry(&mut db, name, group)?;
+  debug!("Moved entry {} to {}", name, group);
+  write_database(options, &mut db, &key).await?;
+  Ok(())
+}
+
+async fn command_group_list(options: &KeeOptions, format: Format) -> Result<()> {
+  let db = get_database(options, &get_database_key(options)?).await?;
+  let groups = list_groups(&db);
+
+  match format {
+    Format::Text => {
+      for group in groups.iter() {
+        println!("{}/", group.path);
+      }
+    }
+    _ => println!("{}", render(format, &groups)?),
+  }
+  Ok(())
+}
+
+async fn command_group(options: &KeeOptions, command: &GroupCommands) -> Result<()> {
+  let key = get_database_key(options)?;
+  let mut db = get_database(options, &key).await?;
+
+  match command {
+    GroupCommands::Create { path } => create_group(&mut db, path)?,
+    GroupCommands::Rename { path, new_name } => rename_group(&mut db, path, new_name)?,
+    GroupCommands::Delete {
+      path,
+      recursive,
+      permanent,
+    } => delete_group(&mut db, path, *recursive, *permanent)?,
+    GroupCommands::Move { path, parent } => move_group(&mut db, path, parent)?,
+    GroupCommands::List => unreachable!(),
+  }
+
+  write_database(options, &mut db, &key).await?;
+  Ok(())
+}
+
+fn command_gen(
+  length: &usize,
+  passphrase: &bool,
+  options: PassphraseOptions,
+  wordlist: &Option<String>,
+) -> Result<()> {
+  if !passphrase {
+    println!("{}", generate_password(length));
+    return Ok(());
+  }
+
+  let wordlist = match wordlist {
+    Some(path) => Some(fs::read_to_string(path)?),
+    None => None,
+  };
+
+  println!("{}", generate_passphrase(&options, wordlist.as_deref())?);
+  Ok(())
+}
+
+async fn command_create(path: &str) -> Result<()> {
+  let db = create_database()?;
+
+  let mut key = DatabaseKey::new();
+  let pw = read_password("Create a password".to_string());
+  key = key.with_password(pw.as_str());
+
+  db.save(&mut File::create(path)?, key)?;
+
+  Ok(())
+}
+
+async fn command_delete(
+  options: &KeeOptions,
+  name: &str,
+  permanent: &bool,
+) -> Result<()> {
+  let key = get_database_key(options)?;
+  let mut db = get_database(options, &key).await?;
+  delete_entry(&mut db, name, *permanent)?;
+  debug!("Deleted entry {}", name);
+  write_database(options, &mut db, &key).await?;
+  Ok(())
+}
+
+async fn command_trash_list(options: &KeeOptions, format: Format) -> Result<()> {
+  let db = get_database(options, &get_database_key(options)?).await?;
+  let items = list_trash(&db);
+
+  if format != Format::Text {
+    println!("{}", render(format, &items)?);
+    return Ok(());
+  }
+
+  if items.is_empty() {
+    println!("Recycle bin is empty");
+  }
+
+  for item in items.iter() {
+    let from = match &item.previous_parent {
+      Some(path) if !path.is_empty() => format!("from {}/", path),
+      Some(_) => "from /".to_string(),
+      None => "".to_string(),
+    };
+    let suffix = if item.group { "/" } else { "" };
+    println!(
+      "{}  {}{}  {}",
+      format_time(&item.deleted),
+      item.title,
+      suffix,
+      from.bright_black()
+    );
+  }
+  Ok(())
+}
+
+async fn command_trash_restore(options: &KeeOptions, name: &str) -> Result<()> {
+  let key = get_database_key(options)?;
+  let mut db = get_database(options, &key).await?;
+  restore_trash_entry(&mut db, name)?;
+  debug!("Restored entry {}", name);
+  write_database(options, &mut db, &key).await?;
+  Ok(())
+}
+
+async fn command_trash_empty(options: &KeeOptions) -> Result<()> {
+  let key = get_database_key(options)?;
+  let mut db = get_database(options, &key).await?;
+  let count = empty_trash(&mut db);
+  write_database(options, &mut db, &key).await?;
+  println!("Deleted {} entries", count);
+  Ok(())
+}
+
+/// Environment variable the completion script sets to ask for completions.
+const COMPLETE_VAR: &str = "COMPLETE";
+
+fn command_completions(shell: Shell) -> Result<()> {
+  let shells = Shells::builtins();
+  let completer = shells
+    .completer(&shell.to_string())
+    .ok_or(anyhow!("Completions for {} are not supported", shell))?;
+
+  // the script calls back into this binary, like CompleteEnv does
+  let mut bin = PathBuf::from(env::args_os().next().unwrap_or("key".into()));
+  if bin.components().count() > 1 {
+    bin = env::current_dir()?.join(bin);
+  }
+  completer.write_registration(
+    COMPLETE_VAR,
+    "key",
+    "key",
+    &bin.to_string_lossy(),
+    &mut io::stdout(),
+  )?;
+  Ok(())
+}
+
+/// Entry paths starting with what has been typed so far. Completions must not
+/// prompt, so without credentials in the environment there are none.
+fn complete_entry(current: &OsStr) -> Vec<CompletionCandidate> {
+  let current = current.to_string_lossy();
+  match completion_database() {
+    Ok(db) => list_entries(&db)
+      .into_iter()
+      .filter(|(path, _)| path.starts_with(current.as_ref()))
+      .map(|(path, _)| CompletionCandidate::new(path))
+      .collect(),
+    Err(err) => {
+      debug!("No entry completions: {}", err);
+      Vec::new()
+    }
+  }
+}
+
+/// Opens the database with the credentials from the environment. S3 databases
+/// are read from the cache, completions should not wait for the network.
+fn completion_database() -> Result<Database> {
+  let options = KeeOptions::from_env()?;
+  if options.keepassdb_keyfile.is_none() && options.keepassdb_password.is_none() {
+    return Err(anyhow!("Neither KEY_PASSWORD nor KEY_KEYFILE is set"));
+  }
+
+  let url = Url::parse(&options.keepassdb)?;
+  let data = match url.scheme() {
+    "file" => fs::read(url.path())?,
+    "s3" => get_cache_database(
+      url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .unwrap_or_default()
+        .to_string(),
+    )?,
+    schema => return Err(anyhow!("Unsupported schema \"{}\"", schema)),
+  };
+  let key = key::db::get_database_key(&options)?;
+  Ok(Database::open(&mut data.as_slice(), key)?)
+}
+
+/// Arguments of the process. Invoked as docker-credential-key, through a link
+/// or copy of the binary, they are taken as `key docker-credential <operation>`.
+fn cli_args() -> Vec<OsString> {
+  let mut args: Vec<OsString> = env::args_os().collect();
+  let program = args
+    .first()
+    .and_then(|arg| Path::new(arg).file_stem())
+    .and_then(|stem| stem.to_str());
+  if program == Some("docker-credential-key") {
+    args.insert(1, OsString::from("docker-credential"));
+  }
+  args
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+  env_logger::init();
+
+  CompleteEnv::with_factory(Cli::command).complete();
+
+  let cli = Cli::parse_from(cli_args());
+  let format = cli.format;
+
+  let result = run(cli).await;
+  if let Err(err) = &result {
+    if format != Format::Text {
+      eprintln!("{}", render_error(format, err));
+      process::exit(1);
+    }
+  }
+  result
+}
+
+async fn run(cli: Cli) -> Result<()> {
+  // Commands that don't need a database
+  match &cli.command {
+    Some(Commands::Create { path }) => return command_create(path).await,
+    Some(Commands::Completions { shell }) => return command_completions(*shell),
+    Some(Commands::ClearClipboard { after }) => {
+      return command_clear_clipboard(*after).await
+    }
+    Some(Commands::Gen {
+      length,
+      passphrase,
+      words,
+      separator,
+      capitalize,
+      number,
+      wordlist,
+    }) => {
+      let options = PassphraseOptions {
+        words: *words,
+        separator: separator.clone(),
+        capitalize: *capitalize,
+        number: *number,
+      };
+      return command_gen(length, passphrase, options, wordlist);
+    }
+    _ => {}
+  }
+
+  let mut vaults = vaults_from_cli(&cli)?;
+
+  // Commands that work with several vaults
+  match &cli.command {
+    Some(Commands::List { output, tag }) => {
+      return match output {
+        Some(Format::Json) => command_list(&vaults, Format::Json, true, tag).await,
+        Some(output) => command_list(&vaults, *output, false, tag).await,
+        None => command_list(&vaults, cli.format, false, tag).await,
+      }
+    }
+    Some(Commands::Search { query }) => {
+      return command_search(&vaults, query, cli.format).await
+    }
+    Some(Commands::Cp {
+      name,
+      new_name,
+      from,
+      to,
+    }) => return command_cp(&cli, vaults, name, new_name, from, to).await,
+    _ => {}
+  }
+
+  if vaults.len() > 1 {
+    return Err(anyhow!("Only list, search and cp work with several vaults"));
+  }
+  let options = vaults.remove(0).options;
+
+  debug!("options {:?}", options);
+
+  match &cli.command {
+    Some(Commands::Get {
+      name,
+      field,
+      file,
       clipboard,
     }) => {
-      if file.clone() == true {
+      let field = field.as_deref().unwrap_or(&options.default_field);
+      if *file {
         return command_get_file(&options, name, field).await;
       }
-      return command_get(&options, name, field, clipboard).await;
+      return command_get(&options, name, field, clipboard, cli.format).await;
+    }
+    Some(Commands::History {
+      command: Some(HistoryCommands::Restore { name, index }),
+      ..
+    }) => command_history_restore(&options, name, index).await,
+    Some(Commands::History {
+      command: None,
+      name: Some(name),
+      diff,
+    }) => command_history(&options, name, diff, cli.format).await,
+    Some(Commands::History { .. }) => unreachable!(),
+    Some(Commands::Show { name, reveal }) => {
+      command_show(&options, name, reveal, cli.format).await
     }
     Some(Commands::Choose {
       clipboard,
       field,
       otp,
-    }) => command_choose(&options, field, clipboard, otp).await,
-    Some(Commands::OTP {
+      tag,
+    }) => {
+      let field = field.as_deref().unwrap_or(&options.default_field);
+      command_choose(&options, field, clipboard, otp, tag).await
+    }
+    Some(Commands::Tui { lock_after }) => command_tui(&options, lock_after).await,
+    Some(Commands::Tag { command }) => command_tag(&options, command).await,
+    Some(Commands::Import {
+      file,
+      from,
+      group,
+      dry_run,
+      skip_attachments,
+      source_keyfile,
+    }) => {
+      command_import(
+        &options,
+        file,
+        *from,
+        group,
+        dry_run,
+        skip_attachments,
+        source_keyfile,
+        cli.format,
+      )
+      .await
+    }
+    Some(Commands::Export {
+      out,
+      to,
+      include_secrets,
+      group,
+      tag,
+      new_password,
+      new_keyfile,
+    }) => {
+      let export = ExportOptions {
+        include_secrets: *include_secrets,
+        group: group.clone(),
+        tags: tag.clone(),
+      };
+      command_export(&options, out, *to, export, new_password, new_keyfile).await
+    }
+    Some(Commands::Extract {
+      group,
+      to,
+      password,
+      keyfile,
+      force,
+    }) => command_extract(&options, group, to, password, keyfile, force).await,
+    Some(Commands::Run {
+      env,
+      env_group,
+      command,
+    }) => command_run(&options, env, env_group, command).await,
+    Some(Commands::DockerCredential { operation, group }) => {
+      let result = command_docker_credential(&options, *operation, group).await;
+      // docker reads errors from stdout
+      if let Err(err) = &result {
+        println!("{}", err);
+        process::exit(1);
+      }
+      result
+    }
+    Some(Commands::SshAdd {
+      name,
+      field,
+      lifetime,
+    }) => command_ssh_add(&options, name, field, lifetime).await,
+    Some(Commands::SshAgent { names, socket }) => {
+      command_ssh_agent(&options, names, socket).await
+    }
+    Some(Commands::GitCredential { operation, group }) => {
+      command_git_credential(&options, *operation, group).await
+    }
+    Some(Commands::Read { uri, clipboard }) => {
+      command_read(&options, uri, clipboard, cli.format).await
+    }
+    Some(Commands::Inject {
+      input,
+      output,
+      check,
+    }) => command_inject(&options, input, output, check).await,
+    Some(Commands::Audit {
+      min_score,
+      max_age,
+      hibp,
+    }) => {
+      let audit = AuditOptions {
+        min_score: *min_score,
+        max_age_days: *max_age,
+        hibp: hibp.clone(),
+      };
+      command_audit(&options, audit, cli.format).await
+    }
+    Some(Commands::SetExpiry { name, expiry }) => {
+      command_set_expiry(&options, name, expiry).await
+    }
+    Some(Commands::Expiring { within }) => {
+      command_expiring(&options, within, cli.format).await
+    }
+    Some(Commands::Otp {
       name,
       field,
       clipboard,
-    }) => command_otp(&options, name, field, clipboard).await,
-    Some(Commands::Set { name, value, field }) => {
-      command_set(&options, name, value, field).await
+    }) => command_otp(&options, name, field, clipboard, cli.format).await,
+    Some(Commands::Set {
+      name,
+      value,
+      field,
+      generate,
+      prompt,
+      from_file,
+      print,
+      clipboard,
+    }) => {
+      let field = field.as_deref().unwrap_or(&options.default_field);
+      let key = get_database_key(&options)?;
+      match generate {
+        Some(policy) => {
+          let value = generate_from_policy(&options, policy)?;
+          command_set(&options, key, name, &value, field).await?;
+          reveal_generated(&options, value, print, clipboard)
+        }
+        None => {
+          let value = read_value(value, prompt, from_file, field)?;
+          command_set(&options, key, name, &value, field).await
+        }
+      }
+    }
+    Some(Commands::New {
+      name,
+      user,
+      url,
+      generate,
+      print,
+      clipboard,
+    }) => command_new(&options, name, user, url, generate, print, clipboard).await,
+    Some(Commands::Delete { name, permanent }) => {
+      command_delete(&options, name, permanent).await
     }
-    Some(Commands::Delete { name }) => command_delete(&options, name).await,
+    Some(Commands::Trash { command }) => match command {
+      TrashCommands::List => command_trash_list(&options, cli.format).await,
+      TrashCommands::Restore { name } => command_trash_restore(&options, name).await,
+      TrashCommands::Empty => command_trash_empty(&options).await,
+    },
     Some(Commands::Rename { name, new_name }) => {
       command_rename(&options, name, new_name).await
     }
-    Some(Commands::Gen { length }) => {
-      println!("{}", generate_password(length));
-      Ok(())
-    }
+    Some(Commands::Mv { name, group }) => command_mv(&options, name, group).await,
+    Some(Commands::Group {
+      command: GroupCommands::List,
+    }) => command_group_list(&options, cli.format).await,
+    Some(Commands::Group { command }) => command_group(&options, command).await,
+    Some(Commands::Create { .. })
+    | Some(Commands::Gen { .. })
+    | Some(Commands::Completions { .. })
+    | Some(Commands::ClearClipboard { .. })
+    | Some(Commands::List { .. })
+    | Some(Commands::Search { .. })
+    | Some(Commands::Cp { .. }) => unreachable!(),
     None => {
       Cli::command().print_help()?;
       println!("No command provided.");
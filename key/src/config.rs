@@ -0,0 +1,136 @@
+use std::{collections::BTreeMap, env, fs, path::PathBuf};
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+
+/// The configuration file at ~/.config/key/config.toml:
+///
+/// ```toml
+/// default_profile = "personal"
+///
+/// [profiles.personal]
+/// url = "file:///home/me/personal.kdbx"
+/// keyfile = "~/.config/key/personal.key"
+/// clipboard_timeout = 30
+/// lock_timeout = 120
+/// generator = "words:5"
+///
+/// [profiles.team]
+/// url = "s3://s3.example.com/vaults/team.kdbx"
+/// s3_access_key = "..."
+/// s3_secret_key = "..."
+/// field = "UserName"
+/// ```
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+  /// Profile used when none is selected
+  pub default_profile: Option<String>,
+  #[serde(default)]
+  pub profiles: BTreeMap<String, Profile>,
+}
+
+/// Settings of a vault. Flags and environment variables take precedence over
+/// all of them.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+  /// Url to the keepass database file
+  pub url: Option<String>,
+  /// Path to the keyfile, "~/" is the home directory
+  pub keyfile: Option<String>,
+  pub s3_access_key: Option<String>,
+  pub s3_secret_key: Option<String>,
+  /// Field `get`, `set` and `choose` use instead of Password
+  pub field: Option<String>,
+  /// Seconds after which copied values are cleared from the clipboard
+  pub clipboard_timeout: Option<u64>,
+  /// Seconds without input after which `key tui` locks the vault
+  pub lock_timeout: Option<u64>,
+  /// Policy for generated passwords, chars[:LENGTH] or words[:COUNT]
+  pub generator: Option<String>,
+}
+
+impl Profile {
+  /// Settings given by environment variables.
+  pub fn from_env() -> Self {
+    Profile {
+      url: env::var("KEY_DATABASE_URL").ok(),
+      keyfile: env::var("KEY_KEYFILE").ok(),
+      s3_access_key: env::var("KEY_S3_ACCESS_KEY").ok(),
+      s3_secret_key: env::var("KEY_S3_SECRET_KEY").ok(),
+      ..Default::default()
+    }
+  }
+
+  /// The settings without keyfile and S3 credentials, which belong to the
+  /// database of the profile and are not used for any other url.
+  pub fn without_key(self) -> Self {
+    Profile {
+      keyfile: None,
+      s3_access_key: None,
+      s3_secret_key: None,
+      ..self
+    }
+  }
+
+  /// Takes every setting this profile leaves out from `fallback`.
+  pub fn or(self, fallback: Profile) -> Self {
+    Profile {
+      url: self.url.or(fallback.url),
+      keyfile: self.keyfile.or(fallback.keyfile),
+      s3_access_key: self.s3_access_key.or(fallback.s3_access_key),
+      s3_secret_key: self.s3_secret_key.or(fallback.s3_secret_key),
+      field: self.field.or(fallback.field),
+      clipboard_timeout: self.clipboard_timeout.or(fallback.clipboard_timeout),
+      lock_timeout: self.lock_timeout.or(fallback.lock_timeout),
+      generator: self.generator.or(fallback.generator),
+    }
+  }
+}
+
+pub fn config_path() -> Result<PathBuf> {
+  let home = home::home_dir().ok_or(anyhow!("Could not determine home directory"))?;
+  Ok(home.join(".config/key/config.toml"))
+}
+
+impl Config {
+  /// Reads the configuration file. Without one, there are no profiles.
+  pub fn load() -> Result<Self> {
+    let path = config_path()?;
+    if !path.exists() {
+      return Ok(Config::default());
+    }
+    let content = fs::read_to_string(&path)?;
+    toml::from_str(&content).map_err(|err| anyhow!("{}: {}", path.display(), err))
+  }
+
+  /// The profile called `name`, or the default profile if no name is given.
+  /// Without either, all settings are left to flags, environment and defaults.
+  pub fn profile(&self, name: Option<&str>) -> Result<Profile> {
+    let name = match name.or(self.default_profile.as_deref()) {
+      Some(name) => name,
+      None => return Ok(Profile::default()),
+    };
+    let mut profile = self.profiles.get(name).cloned().ok_or_else(|| {
+      let names: Vec<&str> = self.profiles.keys().map(String::as_str).collect();
+      match names.is_empty() {
+        true => anyhow!("Profile \"{}\" not found, no profiles are configured", name),
+        false => anyhow!(
+          "Profile \"{}\" not found, available are: {}",
+          name,
+          names.join(", ")
+        ),
+      }
+    })?;
+    profile.keyfile = profile.keyfile.map(expand_home);
+    Ok(profile)
+  }
+}
+
+fn expand_home(path: String) -> String {
+  match (path.strip_prefix("~/"), home::home_dir()) {
+    (Some(rest), Some(home)) => home.join(rest).to_string_lossy().to_string(),
+    _ => path,
+  }
+}
@@ -0,0 +1,496 @@
+use std::collections::BTreeMap;
+
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Serialize};
+use url::Url;
+
+use crate::{
+  backup_entry, delete_entry, ensure_group, entry_exists, find_entry_mut, get_group,
+  list_entries, new_entry, set_entry, split_group_path, Database, Entry, Value,
+  UUID_PREFIX,
+};
+
+/// Credential description of the git credential helper protocol, see
+/// https://git-scm.com/docs/git-credential#IOFMT
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct GitCredential {
+  pub protocol: Option<String>,
+  pub host: Option<String>,
+  pub path: Option<String>,
+  pub username: Option<String>,
+  pub password: Option<String>,
+}
+
+impl GitCredential {
+  /// Reads "key=value" lines up to the first empty line. Attributes we don't
+  /// use are ignored.
+  pub fn parse(input: &str) -> Result<Self> {
+    let mut credential = GitCredential::default();
+    for line in input.lines() {
+      if line.is_empty() {
+        break;
+      }
+      let (key, value) = line
+        .split_once('=')
+        .ok_or(anyhow!("Invalid credential line \"{}\"", line))?;
+      let value = Some(value.to_string());
+      match key {
+        "protocol" => credential.protocol = value,
+        "host" => credential.host = value,
+        "path" => credential.path = value,
+        "username" => credential.username = value,
+        "password" => credential.password = value,
+        "url" => {
+          let url = Url::parse(value.as_deref().unwrap_or_default())?;
+          credential.protocol = Some(url.scheme().to_string());
+          credential.host = Some(host_with_port(&url));
+          credential.path = Some(url.path().trim_start_matches('/').to_string())
+            .filter(|path| !path.is_empty());
+          credential.username =
+            Some(url.username().to_string()).filter(|user| !user.is_empty());
+        }
+        _ => {}
+      }
+    }
+    Ok(credential)
+  }
+
+  /// Url stored in the URL field of entries created for this credential.
+  pub fn url(&self) -> Result<String> {
+    let protocol = self
+      .protocol
+      .as_deref()
+      .ok_or(anyhow!("protocol is missing"))?;
+    let host = self.host.as_deref().ok_or(anyhow!("host is missing"))?;
+    Ok(match &self.path {
+      Some(path) => format!("{}://{}/{}", protocol, host, path),
+      None => format!("{}://{}", protocol, host),
+    })
+  }
+
+  /// The reply to a `get` request, empty if there is no password.
+  pub fn to_reply(&self) -> String {
+    match (&self.username, &self.password) {
+      (Some(username), Some(password)) => {
+        format!("username={}\npassword={}\n", username, password)
+      }
+      (None, Some(password)) => format!("password={}\n", password),
+      _ => String::new(),
+    }
+  }
+}
+
+fn host_with_port(url: &Url) -> String {
+  let host = url.host_str().unwrap_or_default().to_lowercase();
+  match url.port() {
+    Some(port) => format!("{}:{}", host, port),
+    None => host,
+  }
+}
+
+/// Path segments without a trailing ".git", so "org/repo.git" matches "org/repo".
+fn path_segments(path: &str) -> Vec<&str> {
+  path
+    .trim_end_matches('/')
+    .trim_end_matches(".git")
+    .split('/')
+    .filter(|s| !s.is_empty())
+    .collect()
+}
+
+/// How well the url of an entry matches a credential: `None` if it does not,
+/// otherwise the number of matching path segments. Urls without a scheme, like
+/// "github.com", match any protocol.
+fn match_url(url: &str, credential: &GitCredential) -> Option<usize> {
+  let host = credential.host.as_deref()?.to_lowercase();
+  let protocol = credential.protocol.as_deref().unwrap_or("https");
+  let url = match url.contains("://") {
+    true => Url::parse(url).ok()?,
+    false => Url::parse(&format!("{}://{}", protocol, url)).ok()?,
+  };
+
+  if url.scheme() != protocol || host_with_port(&url) != host {
+    return None;
+  }
+
+  let entry_path = path_segments(url.path());
+  let path = credential.path.as_deref().map(path_segments);
+  match path {
+    Some(path) if path.starts_with(&entry_path) => Some(entry_path.len()),
+    Some(_) => None,
+    // without a path, git asks for the whole host
+    None => Some(0),
+  }
+}
+
+/// Finds the entry with the best matching url and the same username, if one is
+/// given. Entries in the recycle bin are left out.
+pub fn find_git_credential<'a>(
+  db: &'a Database,
+  credential: &GitCredential,
+) -> Option<(String, &'a Entry)> {
+  list_entries(db)
+    .into_iter()
+    .filter(|(_, e)| match &credential.username {
+      Some(username) => e.get_username() == Some(username.as_str()),
+      None => true,
+    })
+    .filter_map(|(path, e)| {
+      let score = match_url(e.get_url()?, credential)?;
+      Some((score, path, e))
+    })
+    // max_by_key returns the last maximum, so reverse to prefer the first entry
+    .rev()
+    .max_by_key(|(score, _, _)| *score)
+    .map(|(_, path, e)| (path, e))
+}
+
+/// Fills in username and password from the database.
+pub fn get_git_credential(db: &Database, credential: &GitCredential) -> GitCredential {
+  let mut reply = GitCredential::default();
+  if let Some((_, entry)) = find_git_credential(db, credential) {
+    reply.username = entry.get_username().map(str::to_string);
+    reply.password = entry.get_password().map(str::to_string);
+  }
+  reply
+}
+
+/// Updates the password of the matching entry, or creates an entry named after
+/// the host in `group`. If an entry of another url already has that name, the
+/// username or a number is added. Returns whether the database changed, git
+/// also stores credentials that just came from us.
+pub fn store_git_credential(
+  db: &mut Database,
+  credential: &GitCredential,
+  group: &str,
+) -> Result<bool> {
+  let password = credential
+    .password
+    .as_deref()
+    .ok_or(anyhow!("password is missing"))?;
+
+  if let Some((_, entry)) = find_git_credential(db, credential) {
+    if entry.get_password() == Some(password) {
+      return Ok(false);
+    }
+    let name = format!("{}{}", UUID_PREFIX, entry.uuid);
+    set_entry(db, &name, password, "Password")?;
+    return Ok(true);
+  }
+
+  let host = credential
+    .host
+    .as_deref()
+    .ok_or(anyhow!("host is missing"))?;
+  let parts = split_group_path(group);
+  let prefix: String = parts.iter().map(|p| format!("{}/", p)).collect();
+  let mut name = format!("{}{}", prefix, host);
+  if let Some(username) = credential.username.as_deref() {
+    if entry_exists(&db.root, &name) {
+      name = format!("{}{} ({})", prefix, host, username);
+    }
+  }
+  let taken = name.clone();
+  let mut i = 2;
+  while entry_exists(&db.root, &name) {
+    name = format!("{} {}", taken, i);
+    i += 1;
+  }
+
+  ensure_group(&mut db.root, &parts);
+  new_entry(
+    db,
+    &name,
+    credential.username.as_deref(),
+    Some(&credential.url()?),
+  )?;
+  // set directly, set_entry would keep the empty entry in the history
+  find_entry_mut(&mut db.root, &name)?
+    .fields
+    .insert("Password".to_string(), Value::protected(password));
+  Ok(true)
+}
+
+/// Moves the matching entry to the recycle bin, but only if it holds the
+/// password git reports as rejected. Returns whether an entry was erased.
+pub fn erase_git_credential(
+  db: &mut Database,
+  credential: &GitCredential,
+) -> Result<bool> {
+  let uuid = match find_git_credential(db, credential) {
+    Some((_, entry)) if entry.get_password() == credential.password.as_deref() => {
+      entry.uuid
+    }
+    _ => return Ok(false),
+  };
+  delete_entry(db, &format!("{}{}", UUID_PREFIX, uuid), false)?;
+  Ok(true)
+}
+
+/// Message docker expects from `get` when there are no credentials, see
+/// https://github.com/docker/docker-credential-helpers
+pub const DOCKER_CREDENTIALS_NOT_FOUND: &str = "credentials not found in native keychain";
+
+/// Registry credentials as docker credential helpers exchange them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "PascalCase")]
+pub struct DockerCredential {
+  #[serde(rename = "ServerURL")]
+  pub server_url: String,
+  pub username: String,
+  pub secret: String,
+}
+
+/// Server urls without scheme and trailing slash, so "https://ghcr.io/" and
+/// "ghcr.io" are the same registry.
+fn normalize_server_url(server_url: &str) -> &str {
+  let url = server_url.trim();
+  let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+  url.trim_end_matches('/')
+}
+
+/// Entries directly in `group` that hold registry credentials, by server url.
+fn docker_entries<'a>(db: &'a Database, group: &str) -> Vec<&'a Entry> {
+  match get_group(db, group) {
+    Ok(group) => group
+      .entries
+      .iter()
+      .filter(|e| e.get_url().is_some())
+      .collect(),
+    Err(_) => Vec::new(),
+  }
+}
+
+fn find_docker_entry<'a>(
+  db: &'a Database,
+  group: &str,
+  server_url: &str,
+) -> Option<&'a Entry> {
+  let server_url = normalize_server_url(server_url);
+  docker_entries(db, group)
+    .into_iter()
+    .find(|e| e.get_url().map(normalize_server_url) == Some(server_url))
+}
+
+pub fn get_docker_credential(
+  db: &Database,
+  group: &str,
+  server_url: &str,
+) -> Option<DockerCredential> {
+  let entry = find_docker_entry(db, group, server_url)?;
+  Some(DockerCredential {
+    server_url: server_url.trim().to_string(),
+    username: entry.get_username().unwrap_or_default().to_string(),
+    secret: entry.get_password().unwrap_or_default().to_string(),
+  })
+}
+
+/// Updates the entry of the registry in `group` or creates one named after the
+/// registry host. Returns whether the database changed.
+pub fn store_docker_credential(
+  db: &mut Database,
+  group: &str,
+  credential: &DockerCredential,
+) -> Result<bool> {
+  if let Some(entry) = find_docker_entry(db, group, &credential.server_url) {
+    if entry.get_username() == Some(credential.username.as_str())
+      && entry.get_password() == Some(credential.secret.as_str())
+    {
+      return Ok(false);
+    }
+    let name = format!("{}{}", UUID_PREFIX, entry.uuid);
+    let entry = find_entry_mut(&mut db.root, &name)?;
+    backup_entry(entry, &db.meta);
+    entry.fields.insert(
+      "UserName".to_string(),
+      Value::Unprotected(credential.username.clone()),
+    );
+    entry.fields.insert(
+      "Password".to_string(),
+      Value::protected(credential.secret.clone()),
+    );
+    return Ok(true);
+  }
+
+  let host = normalize_server_url(&credential.server_url)
+    .split('/')
+    .next()
+    .filter(|host| !host.is_empty())
+    .ok_or(anyhow!("Invalid server url \"{}\"", credential.server_url))?;
+  let parts = split_group_path(group);
+  let prefix: String = parts.iter().map(|p| format!("{}/", p)).collect();
+  let mut name = format!("{}{}", prefix, host);
+  if entry_exists(&db.root, &name) {
+    name = format!("{}{} ({})", prefix, host, credential.username);
+  }
+
+  ensure_group(&mut db.root, &parts);
+  new_entry(
+    db,
+    &name,
+    Some(&credential.username),
+    Some(&credential.server_url),
+  )?;
+  find_entry_mut(&mut db.root, &name)?.fields.insert(
+    "Password".to_string(),
+    Value::protected(credential.secret.clone()),
+  );
+  Ok(true)
+}
+
+/// Moves the entry of the registry to the recycle bin. Returns whether there
+/// was one.
+pub fn erase_docker_credential(
+  db: &mut Database,
+  group: &str,
+  server_url: &str,
+) -> Result<bool> {
+  let uuid = match find_docker_entry(db, group, server_url) {
+    Some(entry) => entry.uuid,
+    None => return Ok(false),
+  };
+  delete_entry(db, &format!("{}{}", UUID_PREFIX, uuid), false)?;
+  Ok(true)
+}
+
+/// Usernames of all stored registries by server url.
+pub fn list_docker_credentials(db: &Database, group: &str) -> BTreeMap<String, String> {
+  docker_entries(db, group)
+    .into_iter()
+    .filter_map(|e| {
+      let username = e.get_username().unwrap_or_default().to_string();
+      Some((e.get_url()?.to_string(), username))
+    })
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use keepass::config::DatabaseConfig;
+
+  use super::*;
+
+  fn credential(input: &str) -> GitCredential {
+    GitCredential::parse(input).unwrap()
+  }
+
+  #[test]
+  fn parse_attributes() {
+    let parsed = credential(
+      "protocol=https\nhost=github.com\npath=org/repo.git\nusername=tim\n\
+       capability[]=authtype\n\nhost=ignored.com\n",
+    );
+    assert_eq!(
+      parsed,
+      GitCredential {
+        protocol: Some("https".to_string()),
+        host: Some("github.com".to_string()),
+        path: Some("org/repo.git".to_string()),
+        username: Some("tim".to_string()),
+        password: None,
+      }
+    );
+  }
+
+  #[test]
+  fn parse_url_attribute() {
+    let parsed = credential("url=https://tim@Git.Example.com:8443/org/repo\n");
+    assert_eq!(parsed.protocol.as_deref(), Some("https"));
+    assert_eq!(parsed.host.as_deref(), Some("git.example.com:8443"));
+    assert_eq!(parsed.path.as_deref(), Some("org/repo"));
+    assert_eq!(parsed.username.as_deref(), Some("tim"));
+
+    let parsed = credential("url=https://github.com/\n");
+    assert_eq!(parsed.path, None);
+    assert_eq!(parsed.username, None);
+  }
+
+  #[test]
+  fn parse_rejects_invalid_lines() {
+    assert!(GitCredential::parse("protocol https\n").is_err());
+    assert!(GitCredential::parse("url=not a url\n").is_err());
+  }
+
+  #[test]
+  fn match_url_host_and_protocol() {
+    let github = credential("protocol=https\nhost=github.com\n");
+    assert_eq!(match_url("https://github.com", &github), Some(0));
+    assert_eq!(match_url("github.com", &github), Some(0));
+    // without a path in the credential, any entry of the host matches
+    assert_eq!(match_url("https://GitHub.com/org", &github), Some(0));
+    assert_eq!(match_url("http://github.com", &github), None);
+    assert_eq!(match_url("https://gitlab.com", &github), None);
+    assert_eq!(match_url("https://github.com:8443", &github), None);
+  }
+
+  #[test]
+  fn match_url_path_prefix() {
+    let repo = credential("protocol=https\nhost=github.com\npath=org/repo.git\n");
+    assert_eq!(match_url("https://github.com", &repo), Some(0));
+    assert_eq!(match_url("https://github.com/org", &repo), Some(1));
+    assert_eq!(match_url("https://github.com/org/repo", &repo), Some(2));
+    assert_eq!(
+      match_url("https://github.com/org/repo.git/", &repo),
+      Some(2)
+    );
+    assert_eq!(match_url("https://github.com/other", &repo), None);
+    assert_eq!(match_url("https://github.com/org/repo/sub", &repo), None);
+  }
+
+  #[test]
+  fn store_get_and_erase() {
+    let mut db = Database::new(DatabaseConfig::default());
+    let mut stored = credential("protocol=https\nhost=github.com\nusername=tim\n");
+    stored.password = Some("pw".to_string());
+
+    assert!(store_git_credential(&mut db, &stored, "git").unwrap());
+    assert!(!store_git_credential(&mut db, &stored, "git").unwrap());
+
+    let query = credential("protocol=https\nhost=github.com\npath=org/repo.git\n");
+    let (path, _) = find_git_credential(&db, &query).unwrap();
+    assert_eq!(path, "git/github.com");
+    assert_eq!(
+      get_git_credential(&db, &query).to_reply(),
+      "username=tim\npassword=pw\n"
+    );
+
+    let other = credential("protocol=https\nhost=github.com\nusername=bob\n");
+    assert_eq!(get_git_credential(&db, &other).to_reply(), "");
+
+    let mut rejected = stored.clone();
+    rejected.password = Some("old".to_string());
+    assert!(!erase_git_credential(&mut db, &rejected).unwrap());
+    assert!(erase_git_credential(&mut db, &stored).unwrap());
+    assert_eq!(get_git_credential(&db, &query).to_reply(), "");
+  }
+
+  #[test]
+  fn store_picks_a_free_title() {
+    let mut db = Database::new(DatabaseConfig::default());
+    let store = |db: &mut Database, input: &str| {
+      let mut stored = credential(input);
+      stored.password = Some("pw".to_string());
+      store_git_credential(db, &stored, "git").unwrap()
+    };
+
+    assert!(store(&mut db, "protocol=https\nhost=example.com\n"));
+    assert!(store(&mut db, "protocol=ssh\nhost=example.com\n"));
+    assert!(store(&mut db, "protocol=http\nhost=example.com\n"));
+    assert!(store(
+      &mut db,
+      "protocol=ftp\nhost=example.com\nusername=tim\n"
+    ));
+
+    let mut titles: Vec<String> = list_entries(&db).into_iter().map(|(p, _)| p).collect();
+    titles.sort();
+    assert_eq!(
+      titles,
+      vec![
+        "git/example.com",
+        "git/example.com (tim)",
+        "git/example.com 2",
+        "git/example.com 3",
+      ]
+    );
+  }
+}
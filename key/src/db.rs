@@ -2,9 +2,10 @@ use anyhow::{anyhow, Result};
 use keepass::{Database, DatabaseKey};
 use log::{debug, info};
 use minio::s3::{
-  args::{BucketExistsArgs, ObjectConditionalReadArgs, PutObjectArgs},
+  args::{BucketExistsArgs, ObjectConditionalReadArgs, PutObjectArgs, StatObjectArgs},
   client::Client,
   creds::StaticProvider,
+  error::Error as S3Error,
   http::BaseUrl,
 };
 use std::{
@@ -15,6 +16,8 @@ use std::{
 };
 use url::Url;
 
+use crate::config::{Config, Profile};
+
 #[derive(Debug)]
 pub struct S3Location {
   pub bucket: String,
@@ -37,14 +40,12 @@ pub fn parse_s3_url(dburl_parsed: Url) -> S3Location {
 pub fn get_s3_client(options: &KeeOptions, dburl_parsed: &Url) -> Result<Client> {
   let base_url: BaseUrl = dburl_parsed.host_str().unwrap().parse::<BaseUrl>()?;
 
-  if options.s3_access_key.is_some() && options.s3_secret_key.is_some() {
+  if let (Some(access_key), Some(secret_key)) =
+    (&options.s3_access_key, &options.s3_secret_key)
+  {
     debug!("Using provided S3 credentials");
 
-    let static_provider = StaticProvider::new(
-      options.s3_access_key.as_ref().unwrap().as_str(),
-      options.s3_secret_key.as_ref().unwrap().as_str(),
-      None,
-    );
+    let static_provider = StaticProvider::new(access_key, secret_key, None);
 
     let client = Client::new(
       base_url.clone(),
@@ -66,7 +67,12 @@ pub async fn get_database(options: &KeeOptions, key: &DatabaseKey) -> Result<Dat
   let dburl = &options.keepassdb.as_str();
   let dburl_parsed = Url::parse(dburl)?;
   let schema = dburl_parsed.scheme();
-  let name = dburl_parsed.path().split('/').last().unwrap().to_string();
+  let name = dburl_parsed
+    .path()
+    .split('/')
+    .next_back()
+    .unwrap()
+    .to_string();
 
   let source = match schema {
     "file" => {
@@ -76,7 +82,7 @@ pub async fn get_database(options: &KeeOptions, key: &DatabaseKey) -> Result<Dat
       Ok(buffer)
     }
     "s3" => {
-      let client = get_s3_client(&options, &dburl_parsed)?;
+      let client = get_s3_client(options, &dburl_parsed)?;
       let s3_location = parse_s3_url(dburl_parsed);
 
       debug!("Reading from {:?}", s3_location);
@@ -149,13 +155,34 @@ pub async fn write_database(
   }
 }
 
+/// Whether there already is a file or S3 object at the database url.
+pub async fn database_exists(options: &KeeOptions) -> Result<bool> {
+  let dburl_parsed = Url::parse(&options.keepassdb)?;
+
+  match dburl_parsed.scheme() {
+    "file" => Ok(PathBuf::from(dburl_parsed.path()).exists()),
+    "s3" => {
+      let client = get_s3_client(options, &dburl_parsed)?;
+      let s3_location = parse_s3_url(dburl_parsed);
+      let args = StatObjectArgs::new(&s3_location.bucket, &s3_location.object)?;
+
+      match client.stat_object(&args).await {
+        Ok(_) => Ok(true),
+        Err(S3Error::S3Error(err)) if err.code == "NoSuchKey" => Ok(false),
+        Err(err) => Err(err.into()),
+      }
+    }
+    schema => Err(anyhow::format_err!("Unsupported schema \"{}\"", schema)),
+  }
+}
+
 pub async fn upload_to_s3(
   options: &KeeOptions,
   file: &mut dyn std::io::Read,
   length: u64,
 ) -> Result<()> {
   let dburl_parsed = Url::parse(&options.keepassdb)?;
-  let client = get_s3_client(&options, &dburl_parsed)?;
+  let client = get_s3_client(options, &dburl_parsed)?;
   let s3_location = parse_s3_url(dburl_parsed);
 
   // Check 'bucket_name' bucket exist or not.
@@ -191,39 +218,70 @@ pub async fn upload_to_s3(
   Ok(())
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct KeeOptions {
   pub keepassdb: String,
   pub keepassdb_keyfile: Option<String>,
   pub keepassdb_password: Option<String>,
   pub s3_access_key: Option<String>,
   pub s3_secret_key: Option<String>,
+  /// Field read and written when none is given
+  pub default_field: String,
+  /// Seconds after which copied values are cleared from the clipboard
+  pub clipboard_timeout: Option<u64>,
+  /// Seconds without input after which the tui locks, 0 never locks
+  pub lock_timeout: Option<u64>,
+  /// Policy for generated passwords when none is given
+  pub generator: Option<String>,
 }
 
-impl From<env::Vars> for KeeOptions {
-  fn from(mut vars: env::Vars) -> Self {
-    KeeOptions {
-      keepassdb: vars
-        .find(|v| v.0 == "KEY_DATABASE_URL")
-        .map(|v| v.1)
-        .expect("Missing db url"),
-      keepassdb_keyfile: vars.find(|v| v.0 == "KEY_KEYFILE").map(|v| v.1),
-      keepassdb_password: vars.find(|v| v.0 == "KEY_PASSWORD").map(|v| v.1),
-      s3_access_key: vars.find(|v| v.0 == "KEY_S3_ACCESS_KEY").map(|v| v.1),
-      s3_secret_key: vars.find(|v| v.0 == "KEY_S3_SECRET_KEY").map(|v| v.1),
-    }
+impl KeeOptions {
+  /// Options from the settings, which flags, environment and profile have
+  /// already been layered into. Fails without a database url.
+  pub fn new(settings: Profile, password: Option<String>) -> Result<Self> {
+    Ok(KeeOptions {
+      keepassdb: settings.url.ok_or(anyhow!("No database url provided."))?,
+      keepassdb_keyfile: settings.keyfile,
+      keepassdb_password: password,
+      s3_access_key: settings.s3_access_key,
+      s3_secret_key: settings.s3_secret_key,
+      default_field: settings.field.unwrap_or("Password".to_string()),
+      clipboard_timeout: settings.clipboard_timeout.filter(|timeout| *timeout > 0),
+      lock_timeout: settings.lock_timeout,
+      generator: settings.generator,
+    })
+  }
+
+  /// Options from the KEY_* environment variables, on top of the (first)
+  /// profile in KEY_PROFILE or the default profile. A profile selected in
+  /// KEY_PROFILE keeps its url, otherwise KEY_DATABASE_URL replaces it together
+  /// with the keyfile and S3 credentials of the profile.
+  pub fn from_env() -> Result<Self> {
+    let profiles = env::var("KEY_PROFILE").unwrap_or_default();
+    let selected = profiles.split(',').next().filter(|name| !name.is_empty());
+    let profile = Config::load()?.profile(selected)?;
+    let env = Profile::from_env();
+    let settings = match (&env.url, selected) {
+      (Some(_), None) => env.or(profile.without_key()),
+      _ => Profile { url: None, ..env }.or(profile),
+    };
+    KeeOptions::new(settings, env::var("KEY_PASSWORD").ok())
   }
 }
 
 impl Default for KeeOptions {
   fn default() -> Self {
-    return Self {
+    Self {
       keepassdb: "".to_string(),
       keepassdb_keyfile: None,
       keepassdb_password: None,
       s3_access_key: None,
       s3_secret_key: None,
-    };
+      default_field: "Password".to_string(),
+      clipboard_timeout: None,
+      lock_timeout: None,
+      generator: None,
+    }
   }
 }
 
@@ -267,10 +325,10 @@ pub fn create_database() -> Result<Database> {
   Ok(Database::new(keepass::config::DatabaseConfig::default()))
 }
 
-pub fn cache_database(name: String, file: &Vec<u8>) -> Result<()> {
+pub fn cache_database(name: String, file: &[u8]) -> Result<()> {
   let dir = cache_dir()?;
   let mut cache_file = File::create(dir.join(name))?;
-  cache_file.write(file)?;
+  cache_file.write_all(file)?;
   Ok(())
 }
 
@@ -0,0 +1,114 @@
+use anyhow::{anyhow, Result};
+use chrono::{Days, Months, NaiveDate, NaiveDateTime};
+use keepass::db::Times;
+use serde::{Deserialize, Serialize};
+
+use crate::{backup_entry, find_entry_mut, list_entries, Database, Entry};
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct KeyExpiringEntry {
+  pub uuid: String,
+  pub path: String,
+  pub title: String,
+  pub expiry_time: NaiveDateTime,
+  pub expired: bool,
+}
+
+/// Expiry time of an entry, if it is set to expire.
+pub fn entry_expiry(entry: &Entry) -> Option<NaiveDateTime> {
+  entry
+    .times
+    .expiry
+    .filter(|_| entry.times.expires == Some(true))
+}
+
+pub fn is_expired(entry: &Entry) -> bool {
+  entry_expiry(entry).is_some_and(|expiry| expiry <= Times::now())
+}
+
+/// Adds a period like "30d", "12w", "6m" or "1y" (days, weeks, months, years)
+/// to `from`. A leading "+" is allowed.
+pub fn add_period(from: NaiveDateTime, period: &str) -> Result<NaiveDateTime> {
+  let period = period.trim().trim_start_matches('+');
+  let invalid = || anyhow!("Invalid period \"{}\", use e.g. 30d, 12w, 6m or 1y", period);
+  if period.len() < 2 {
+    return Err(invalid());
+  }
+
+  let (count, unit) = period.split_at(period.len() - 1);
+  let count: u32 = count.parse().map_err(|_| invalid())?;
+  match unit {
+    "d" => from.checked_add_days(Days::new(count.into())),
+    "w" => from.checked_add_days(Days::new(u64::from(count) * 7)),
+    "m" => from.checked_add_months(Months::new(count)),
+    "y" => count
+      .checked_mul(12)
+      .and_then(|months| from.checked_add_months(Months::new(months))),
+    _ => return Err(invalid()),
+  }
+  .ok_or_else(invalid)
+}
+
+/// Parses an expiry given as date ("2025-12-31"), date and time
+/// ("2025-12-31 12:00:00"), period from now ("+90d") or "never".
+pub fn parse_expiry(input: &str) -> Result<Option<NaiveDateTime>> {
+  let input = input.trim();
+  if input == "never" {
+    return Ok(None);
+  }
+  if input.starts_with('+') {
+    return add_period(Times::now(), input).map(Some);
+  }
+  if let Ok(date) = NaiveDate::parse_from_str(input, "%Y-%m-%d") {
+    return Ok(date.and_hms_opt(0, 0, 0));
+  }
+  ["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S"]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(input, format).ok())
+    .map(Some)
+    .ok_or(anyhow!(
+      "Invalid expiry \"{}\", use a date (2025-12-31), a period (+90d) or \"never\"",
+      input
+    ))
+}
+
+/// Sets when an entry expires. `None` makes it never expire.
+pub fn set_entry_expiry(
+  db: &mut Database,
+  name: &str,
+  expiry: Option<NaiveDateTime>,
+) -> Result<()> {
+  let entry = find_entry_mut(&mut db.root, name)?;
+  backup_entry(entry, &db.meta);
+  match expiry {
+    Some(expiry) => {
+      entry.times.expires = Some(true);
+      entry.times.expiry = Some(expiry);
+    }
+    None => entry.times.expires = Some(false),
+  }
+  Ok(())
+}
+
+/// Entries that have expired or expire within `period` ("30d"), soonest first.
+/// Entries in the recycle bin are left out.
+pub fn expiring_entries(db: &Database, period: &str) -> Result<Vec<KeyExpiringEntry>> {
+  let now = Times::now();
+  let until = add_period(now, period)?;
+
+  let mut entries: Vec<KeyExpiringEntry> = list_entries(db)
+    .into_iter()
+    .filter_map(|(path, e)| {
+      let expiry_time = entry_expiry(e).filter(|expiry| *expiry <= until)?;
+      Some(KeyExpiringEntry {
+        uuid: e.uuid.to_string(),
+        title: e.get_title().unwrap_or_default().to_string(),
+        path,
+        expiry_time,
+        expired: expiry_time <= now,
+      })
+    })
+    .collect();
+  entries.sort_by_key(|e| e.expiry_time);
+  Ok(entries)
+}
@@ -0,0 +1,338 @@
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::{anyhow, Result};
+use chrono::NaiveDateTime;
+use clap::ValueEnum;
+use keepass::DatabaseKey;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use uuid::Uuid;
+
+use crate::{
+  entry_expiry, get_group, has_tag, list_entries, output, split_group_path, Database,
+  Entry, Group, Value,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ExportFormat {
+  /// KeePassXC compatible CSV
+  Csv,
+  /// Entries as json, in the same envelope as the json output
+  Json,
+  /// Unencrypted Bitwarden JSON
+  Bitwarden,
+  /// KeePass database, optionally under a different key
+  Kdbx,
+}
+
+/// Which entries to export and what to include.
+#[derive(Default)]
+pub struct ExportOptions {
+  /// Include passwords, OTP secrets and other protected fields
+  pub include_secrets: bool,
+  /// Only export entries below this group ("" for all)
+  pub group: String,
+  /// Only export entries that have all of these tags
+  pub tags: Vec<String>,
+}
+
+/// The exported file and the number of entries in it.
+pub struct Export {
+  pub data: Vec<u8>,
+  pub entries: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct KeyExportEntry {
+  pub uuid: String,
+  pub path: String,
+  pub group: String,
+  pub title: String,
+  pub user: Option<String>,
+  pub url: Option<String>,
+  pub notes: Option<String>,
+  /// Only set when secrets are included
+  pub password: Option<String>,
+  /// Only set when secrets are included
+  pub otp: Option<String>,
+  /// All other fields. Protected ones are only there when secrets are included.
+  pub fields: BTreeMap<String, String>,
+  pub tags: Vec<String>,
+  pub expiry_time: Option<NaiveDateTime>,
+  pub created: Option<NaiveDateTime>,
+  pub modified: Option<NaiveDateTime>,
+}
+
+/// Fields that get their own column or key in every export format
+const STANDARD_FIELDS: [&str; 6] =
+  ["Title", "UserName", "Password", "URL", "Notes", "otp"];
+
+impl KeyExportEntry {
+  /// Protected fields, passwords and one time passwords are left out unless
+  /// `include_secrets`. A protected title is replaced by the uuid, in the path
+  /// as well.
+  pub fn new(path: &str, e: &Entry, include_secrets: bool) -> Self {
+    let field = |field: &str| {
+      let value = e.fields.get(field)?;
+      match include_secrets || !is_secret(field, value) {
+        true => e.get(field).map(str::to_string),
+        false => None,
+      }
+    };
+    let title = field("Title").unwrap_or_else(|| match e.fields.get("Title") {
+      Some(_) => e.uuid.to_string(),
+      None => String::new(),
+    });
+    let group = path.rsplit_once('/').map_or("", |(group, _)| group);
+    let path = match group {
+      "" => title.clone(),
+      group => format!("{}/{}", group, title),
+    };
+    let fields = e
+      .fields
+      .iter()
+      .filter(|(k, v)| {
+        !STANDARD_FIELDS.contains(&k.as_str()) && (include_secrets || !is_secret(k, v))
+      })
+      .filter_map(|(k, _)| Some((k.clone(), e.get(k)?.to_string())))
+      .collect();
+
+    KeyExportEntry {
+      uuid: e.uuid.to_string(),
+      group: group.to_string(),
+      path,
+      title,
+      user: field("UserName"),
+      url: field("URL"),
+      notes: field("Notes"),
+      password: field("Password"),
+      otp: field("otp"),
+      fields,
+      tags: e.tags.clone(),
+      expiry_time: entry_expiry(e),
+      created: e.times.creation,
+      modified: e.times.last_modification,
+    }
+  }
+}
+
+/// Whether a field only goes into exports that include secrets.
+fn is_secret(name: &str, value: &Value<String>) -> bool {
+  matches!(value, Value::Protected(_)) || name == "Password" || name == "otp"
+}
+
+/// Entries outside the recycle bin that match the group and tags of `options`.
+pub fn select_entries<'a>(
+  db: &'a Database,
+  options: &ExportOptions,
+) -> Result<Vec<(String, &'a Entry)>> {
+  get_group(db, &options.group)?;
+  let prefix = split_group_path(&options.group).join("/");
+
+  Ok(
+    list_entries(db)
+      .into_iter()
+      .filter(|(path, _)| {
+        prefix.is_empty()
+          || path
+            .strip_prefix(&prefix)
+            .is_some_and(|rest| rest.starts_with('/'))
+      })
+      .filter(|(_, e)| options.tags.iter().all(|tag| has_tag(e, tag)))
+      .collect(),
+  )
+}
+
+/// Exports the entries selected by `options`. `key` is only needed for
+/// [ExportFormat::Kdbx] and is the key the exported database is saved with.
+pub fn export_entries(
+  db: &Database,
+  format: ExportFormat,
+  options: &ExportOptions,
+  key: Option<DatabaseKey>,
+) -> Result<Export> {
+  let entries = select_entries(db, options)?;
+  let data = match format {
+    ExportFormat::Csv => write_csv(db, &entries, options.include_secrets)?,
+    ExportFormat::Json => {
+      let entries: Vec<KeyExportEntry> = entries
+        .iter()
+        .map(|(path, e)| KeyExportEntry::new(path, e, options.include_secrets))
+        .collect();
+      format!("{}\n", output::render(output::Format::Json, &entries)?).into_bytes()
+    }
+    ExportFormat::Bitwarden => write_bitwarden(db, &entries, options.include_secrets)?,
+    ExportFormat::Kdbx => {
+      let key = key.ok_or(anyhow!("A key is needed to export a kdbx database"))?;
+      write_kdbx(db, &entries, options.include_secrets, key)?
+    }
+  };
+
+  Ok(Export {
+    data,
+    entries: entries.len(),
+  })
+}
+
+/// KeePassXC writes the root group name in front of every group path, which
+/// its importer (and ours) expects.
+fn write_csv(
+  db: &Database,
+  entries: &[(String, &Entry)],
+  include_secrets: bool,
+) -> Result<Vec<u8>> {
+  let mut writer = csv::WriterBuilder::new()
+    .quote_style(csv::QuoteStyle::Always)
+    .from_writer(Vec::new());
+  writer.write_record([
+    "Group",
+    "Title",
+    "Username",
+    "Password",
+    "URL",
+    "Notes",
+    "TOTP",
+    "Icon",
+    "Last Modified",
+    "Created",
+  ])?;
+
+  let format_time = |time: Option<NaiveDateTime>| {
+    time.map(|t| t.format("%Y-%m-%dT%H:%M:%SZ").to_string())
+  };
+  for (path, e) in entries.iter() {
+    let entry = KeyExportEntry::new(path, e, include_secrets);
+    let group = match entry.group.is_empty() {
+      true => db.root.name.clone(),
+      false => format!("{}/{}", db.root.name, entry.group),
+    };
+    writer.write_record([
+      group,
+      entry.title,
+      entry.user.unwrap_or_default(),
+      entry.password.unwrap_or_default(),
+      entry.url.unwrap_or_default(),
+      entry.notes.unwrap_or_default(),
+      entry.otp.unwrap_or_default(),
+      e.icon_id.unwrap_or(0).to_string(),
+      format_time(e.times.last_modification).unwrap_or_default(),
+      format_time(e.times.creation).unwrap_or_default(),
+    ])?;
+  }
+
+  Ok(writer.into_inner()?)
+}
+
+/// Groups become folders named after their path, which the Bitwarden importer
+/// turns back into nested folders.
+fn write_bitwarden(
+  db: &Database,
+  entries: &[(String, &Entry)],
+  include_secrets: bool,
+) -> Result<Vec<u8>> {
+  let mut folders: Vec<String> = Vec::new();
+  let mut items = Vec::new();
+
+  for (path, e) in entries.iter() {
+    let entry = KeyExportEntry::new(path, e, include_secrets);
+    let folder_id = match entry.group.is_empty() {
+      true => None,
+      false => {
+        if !folders.contains(&entry.group) {
+          folders.push(entry.group.clone());
+        }
+        Some(folder_uuid(db, &entry.group))
+      }
+    };
+
+    let fields: Vec<_> = entry
+      .fields
+      .iter()
+      .map(|(name, value)| {
+        let hidden = e.fields.get(name).is_some_and(|v| is_secret(name, v));
+        json!({
+          "name": name,
+          "value": value,
+          // 1 is a hidden field, 0 a text field
+          "type": if hidden { 1 } else { 0 },
+          "linkedId": null,
+        })
+      })
+      .collect();
+
+    items.push(json!({
+      "id": entry.uuid,
+      "organizationId": null,
+      "folderId": folder_id,
+      "type": 1,
+      "reprompt": 0,
+      "name": entry.title,
+      "notes": entry.notes,
+      "favorite": false,
+      "fields": fields,
+      "login": {
+        "uris": entry.url.iter().map(|uri| json!({ "match": null, "uri": uri })).collect::<Vec<_>>(),
+        "username": entry.user,
+        "password": entry.password,
+        "totp": entry.otp,
+      },
+      "collectionIds": null,
+    }));
+  }
+
+  let folders: Vec<_> = folders
+    .iter()
+    .map(|path| json!({ "id": folder_uuid(db, path), "name": path }))
+    .collect();
+  let export = json!({
+    "encrypted": false,
+    "folders": folders,
+    "items": items,
+  });
+  Ok(serde_json::to_vec_pretty(&export)?)
+}
+
+/// Uuid of the group at `path`, used as Bitwarden folder id.
+fn folder_uuid(db: &Database, path: &str) -> String {
+  get_group(db, path)
+    .map(|g| g.uuid)
+    .unwrap_or_default()
+    .to_string()
+}
+
+/// Copies the database with only the selected entries and the groups leading
+/// to them. Uuids, times and history are kept, the recycle bin and the deleted
+/// objects are not. Without secrets, protected fields and the history are left
+/// out.
+fn write_kdbx(
+  db: &Database,
+  entries: &[(String, &Entry)],
+  include_secrets: bool,
+  key: DatabaseKey,
+) -> Result<Vec<u8>> {
+  let keep: HashSet<Uuid> = entries.iter().map(|(_, e)| e.uuid).collect();
+
+  let mut export = db.clone();
+  prune_group(&mut export.root, &keep, include_secrets);
+  export.meta.recyclebin_uuid = None;
+  export.deleted_objects.clear();
+
+  let mut data = Vec::new();
+  export.save(&mut data, key)?;
+  Ok(data)
+}
+
+/// Removes every entry not in `keep` and every group left empty.
+fn prune_group(group: &mut Group, keep: &HashSet<Uuid>, include_secrets: bool) {
+  group.entries.retain_mut(|e| {
+    if !include_secrets {
+      e.fields.retain(|k, v| !is_secret(k, v));
+      e.history = None;
+    }
+    keep.contains(&e.uuid)
+  });
+  group.groups.retain_mut(|g| {
+    prune_group(g, keep, include_secrets);
+    !g.entries.is_empty() || !g.groups.is_empty()
+  });
+}
@@ -0,0 +1,321 @@
+use anyhow::{anyhow, Result};
+use keepass::db::Times;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{entry_exists, find_entry, recycle_bin, Database, Entry, Group, Value};
+
+/// An entry or a group taken out of the tree, see [take_node].
+pub(crate) enum Node {
+  Entry(Entry),
+  Group(Group),
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct KeyGroupInfo {
+  pub uuid: String,
+  pub path: String,
+  pub title: String,
+  /// Number of entries directly in this group
+  pub entries: usize,
+  /// Number of groups directly in this group
+  pub groups: usize,
+}
+
+/// Lists every group below the root as "Group/Sub" paths, leaving out the
+/// recycle bin.
+pub fn list_groups(db: &Database) -> Vec<KeyGroupInfo> {
+  let bin = recycle_bin(db).map(|g| g.uuid);
+  let mut groups = Vec::new();
+  collect_groups(&db.root, "", bin.as_ref(), &mut groups);
+  groups
+}
+
+fn collect_groups(
+  group: &Group,
+  prefix: &str,
+  skip: Option<&Uuid>,
+  groups: &mut Vec<KeyGroupInfo>,
+) {
+  for g in group.groups.iter().filter(|g| Some(&g.uuid) != skip) {
+    let path = format!("{}{}", prefix, g.name);
+    groups.push(KeyGroupInfo {
+      uuid: g.uuid.to_string(),
+      path: path.clone(),
+      title: g.name.clone(),
+      entries: g.entries.len(),
+      groups: g.groups.len(),
+    });
+    collect_groups(g, &format!("{}/", path), skip, groups);
+  }
+}
+
+/// Creates a group at "Group/Sub", including any missing parent groups.
+pub fn create_group(db: &mut Database, path: &str) -> Result<()> {
+  let parts = split_group_path(path);
+  if parts.is_empty() {
+    return Err(anyhow!("Group path is empty"));
+  }
+  if db.root.group_by_path(&parts).is_some() {
+    return Err(anyhow!("\"{}\" already exists", path));
+  }
+
+  ensure_group(&mut db.root, &parts);
+  Ok(())
+}
+
+/// Walks down `parts` from `group`, creating every group that is missing.
+pub(crate) fn ensure_group<'a, S: AsRef<str>>(
+  group: &'a mut Group,
+  parts: &[S],
+) -> &'a mut Group {
+  let mut group = group;
+  for part in parts {
+    let part = part.as_ref();
+    let index = match group.groups.iter().position(|g| g.name == part) {
+      Some(index) => index,
+      None => {
+        group.groups.push(Group::new(part));
+        group.groups.len() - 1
+      }
+    };
+    group = &mut group.groups[index];
+  }
+  group
+}
+
+pub fn rename_group(db: &mut Database, path: &str, new_name: &str) -> Result<()> {
+  if new_name.is_empty() || new_name.contains('/') {
+    return Err(anyhow!("Invalid group name \"{}\"", new_name));
+  }
+  let uuid = get_group(db, path)?.uuid;
+  let mut sibling = split_group_path(path);
+  if sibling.pop().is_some() {
+    sibling.push(new_name);
+    if db
+      .root
+      .group_by_path(&sibling)
+      .is_some_and(|g| g.uuid != uuid)
+    {
+      return Err(anyhow!("Group \"{}\" already exists", sibling.join("/")));
+    }
+  }
+  let group = find_group_mut(&mut db.root, &uuid).expect("Group should exist");
+  group.name = new_name.to_string();
+  group.times.last_modification = Some(Times::now());
+  Ok(())
+}
+
+/// Moves a group with everything in it into another group. An empty parent
+/// path ("" or "/") moves it to the root.
+pub fn move_group(db: &mut Database, path: &str, parent: &str) -> Result<()> {
+  let group = get_group(db, path)?;
+  let uuid = group.uuid;
+  if uuid == db.root.uuid {
+    return Err(anyhow!("Can not move the root group"));
+  }
+  let target = get_group(db, parent)?.uuid;
+  if find_group(group, &target).is_some() {
+    return Err(anyhow!("Can not move \"{}\" into itself", path));
+  }
+
+  let (mut node, _) = take_node(&mut db.root, &uuid).expect("Group should exist");
+  if let Node::Group(g) = &mut node {
+    g.times.location_changed = Some(Times::now());
+  }
+  add_node(
+    find_group_mut(&mut db.root, &target).expect("Group should exist"),
+    node,
+  );
+  Ok(())
+}
+
+/// Moves an entry into the group at `group` ("" or "/" for the root).
+pub fn move_entry(db: &mut Database, name: &str, group: &str) -> Result<()> {
+  let uuid = find_entry(db, name)?.uuid;
+  let target = get_group(db, group)?.uuid;
+
+  let (mut node, _) = take_node(&mut db.root, &uuid).expect("Entry should exist");
+  if let Node::Entry(e) = &mut node {
+    e.times.location_changed = Some(Times::now());
+  }
+  add_node(
+    find_group_mut(&mut db.root, &target).expect("Group should exist"),
+    node,
+  );
+  Ok(())
+}
+
+/// Copies the group at `path` with everything below it into a new database,
+/// where it becomes the root group. Uuids, times, history and attachments are
+/// kept, the recycle bin is left out.
+pub fn extract_group(db: &Database, path: &str) -> Result<Database> {
+  let mut root = get_group(db, path)?.clone();
+  if let Some(bin) = recycle_bin(db) {
+    take_node(&mut root, &bin.uuid);
+  }
+
+  let mut extracted = Database::new(db.config.clone());
+  extracted.meta.database_name = Some(root.name.clone());
+  extracted.meta.memory_protection = db.meta.memory_protection.clone();
+  extracted.meta.history_max_items = db.meta.history_max_items;
+  extracted.meta.history_max_size = db.meta.history_max_size;
+  extracted.root = root;
+  Ok(extracted)
+}
+
+/// Copies an entry into another database as `name` ("Group/Title"), creating
+/// the groups on the way. The copy gets a new uuid and keeps all fields, which
+/// includes the OTP secret, as well as attachments, tags, times and icon. The
+/// history is left behind.
+pub fn copy_entry(
+  from: &Database,
+  entry: &str,
+  to: &mut Database,
+  name: &str,
+) -> Result<()> {
+  if entry_exists(&to.root, name) {
+    return Err(anyhow!("Entry \"{}\" already exists", name));
+  }
+  let mut parts = split_group_path(name);
+  let title = parts
+    .pop()
+    .ok_or(anyhow!("Invalid entry name \"{}\"", name))?;
+
+  let mut copy = find_entry(from, entry)?.clone();
+  copy.uuid = Uuid::new_v4();
+  copy.history = None;
+  copy.times.location_changed = Some(Times::now());
+  copy
+    .fields
+    .insert("Title".to_string(), Value::Unprotected(title.to_string()));
+
+  ensure_group(&mut to.root, &parts).entries.push(copy);
+  Ok(())
+}
+
+/// Finds a group by its path ("Group/Sub"). "" and "/" are the root group.
+pub fn get_group<'a>(db: &'a Database, path: &str) -> Result<&'a Group> {
+  let parts = split_group_path(path);
+  if parts.is_empty() {
+    return Ok(&db.root);
+  }
+  db.root
+    .group_by_path(&parts)
+    .ok_or(anyhow!("Group \"{}\" not found", path))
+}
+
+pub(crate) fn split_group_path(path: &str) -> Vec<&str> {
+  path.split('/').filter(|p| !p.is_empty()).collect()
+}
+
+/// Removes a node from anywhere below `group`, returning it together with the
+/// uuid of the group it was in.
+pub(crate) fn take_node(group: &mut Group, uuid: &Uuid) -> Option<(Node, Uuid)> {
+  if let Some(index) = group.entries.iter().position(|e| e.uuid == *uuid) {
+    return Some((Node::Entry(group.entries.remove(index)), group.uuid));
+  }
+  if let Some(index) = group.groups.iter().position(|g| g.uuid == *uuid) {
+    return Some((Node::Group(group.groups.remove(index)), group.uuid));
+  }
+
+  group.groups.iter_mut().find_map(|g| take_node(g, uuid))
+}
+
+pub(crate) fn add_node(group: &mut Group, node: Node) {
+  match node {
+    Node::Entry(e) => group.entries.push(e),
+    Node::Group(g) => group.groups.push(g),
+  }
+}
+
+pub(crate) fn contains_node(group: &Group, uuid: &Uuid) -> bool {
+  group.entries.iter().any(|e| e.uuid == *uuid)
+    || group
+      .groups
+      .iter()
+      .any(|g| g.uuid == *uuid || contains_node(g, uuid))
+}
+
+pub(crate) fn find_group<'a>(group: &'a Group, uuid: &Uuid) -> Option<&'a Group> {
+  group.group_by_uuid(*uuid)
+}
+
+pub(crate) fn find_group_mut<'a>(
+  group: &'a mut Group,
+  uuid: &Uuid,
+) -> Option<&'a mut Group> {
+  group.group_by_uuid_mut(*uuid)
+}
+
+/// Path of a group below `group` ("Group/Sub"), empty for `group` itself.
+pub(crate) fn group_path(group: &Group, uuid: &Uuid, prefix: &str) -> Option<String> {
+  if group.uuid == *uuid {
+    return Some(prefix.trim_end_matches('/').to_string());
+  }
+  group
+    .groups
+    .iter()
+    .find_map(|g| group_path(g, uuid, &format!("{}{}/", prefix, g.name)))
+}
+
+/// Number of entries in `group` and all groups below it.
+pub(crate) fn count_entries(group: &Group) -> usize {
+  group.entries.len() + group.groups.iter().map(count_entries).sum::<usize>()
+}
+
+#[cfg(test)]
+mod tests {
+  use keepass::config::DatabaseConfig;
+
+  use super::*;
+  use crate::{find_entry_mut, get_entry_attachment, new_entry, Attachment};
+
+  fn database() -> Database {
+    let mut db = Database::new(DatabaseConfig::default());
+    ensure_group(&mut db.root, &["Prod"]);
+    new_entry(&mut db, "Prod/server", Some("admin"), None).unwrap();
+    let entry = find_entry_mut(&mut db.root, "Prod/server").unwrap();
+    let attachment = Attachment {
+      data: Value::protected(b"-----BEGIN".to_vec()),
+    };
+    entry.attachments.insert("id.pem".to_string(), attachment);
+    db
+  }
+
+  #[test]
+  fn rename_refuses_taken_names() {
+    let mut db = database();
+    create_group(&mut db, "Prod/Web").unwrap();
+    create_group(&mut db, "Dev/Web").unwrap();
+
+    let err = rename_group(&mut db, "Dev", "Prod").unwrap_err();
+    assert_eq!(err.to_string(), "Group \"Prod\" already exists");
+    // keeping the name is fine
+    rename_group(&mut db, "Prod/Web", "Web").unwrap();
+
+    rename_group(&mut db, "Dev/Web", "Api").unwrap();
+    create_group(&mut db, "Dev/Web").unwrap();
+    let err = rename_group(&mut db, "Dev/Api", "Web").unwrap_err();
+    assert_eq!(err.to_string(), "Group \"Dev/Web\" already exists");
+  }
+
+  #[test]
+  fn copy_keeps_attachments() {
+    let mut to = Database::new(DatabaseConfig::default());
+    copy_entry(&database(), "Prod/server", &mut to, "Copies/server").unwrap();
+    assert_eq!(
+      get_entry_attachment(&to, "Copies/server", "id.pem").unwrap(),
+      b"-----BEGIN"
+    );
+  }
+
+  #[test]
+  fn extract_keeps_attachments() {
+    let extracted = extract_group(&database(), "Prod").unwrap();
+    assert_eq!(
+      get_entry_attachment(&extracted, "server", "id.pem").unwrap(),
+      b"-----BEGIN"
+    );
+  }
+}
@@ -0,0 +1,174 @@
+use anyhow::{anyhow, Result};
+use chrono::NaiveDateTime;
+use keepass::db::{History, Meta, Times};
+use serde::{Deserialize, Serialize};
+
+use crate::{find_entry, find_entry_mut, Database, Entry, Value};
+
+/// KeePass defaults, used when the database does not set its own limits.
+const DEFAULT_HISTORY_MAX_ITEMS: usize = 10;
+const DEFAULT_HISTORY_MAX_SIZE: usize = 6 * 1024 * 1024;
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct KeyHistoryItem {
+  /// 1 is the most recent previous version
+  pub index: usize,
+  pub title: String,
+  pub modified: Option<NaiveDateTime>,
+  /// Changes from this version to the next newer one
+  #[serde(skip_serializing_if = "Option::is_none")]
+  pub changes: Option<Vec<KeyFieldChange>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+  Added,
+  Removed,
+  Changed,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct KeyFieldChange {
+  pub field: String,
+  pub kind: ChangeKind,
+  /// Values of protected fields are never included
+  pub old: Option<String>,
+  pub new: Option<String>,
+  pub protected: bool,
+}
+
+/// Pushes a snapshot of the entry into its history before it gets modified,
+/// the same way KeePass does, and trims the history to the database limits.
+pub fn backup_entry(entry: &mut Entry, meta: &Meta) {
+  let mut snapshot = entry.clone();
+  snapshot.history = None;
+
+  let mut history = entry.history.take().unwrap_or_default();
+  history.add_entry(snapshot);
+  entry.history = Some(trim_history(history, meta));
+  entry.times.last_modification = Some(Times::now());
+}
+
+fn trim_history(history: History, meta: &Meta) -> History {
+  // a negative limit means unlimited
+  let limit = |value: Option<isize>, default| {
+    value.map_or(default, |v| usize::try_from(v).unwrap_or(usize::MAX))
+  };
+  let max_items = limit(meta.history_max_items, DEFAULT_HISTORY_MAX_ITEMS);
+  let max_size = limit(meta.history_max_size, DEFAULT_HISTORY_MAX_SIZE);
+
+  let mut size = 0;
+  let mut kept: Vec<&Entry> = Vec::new();
+  for item in history.get_entries().iter() {
+    size += entry_size(item);
+    if kept.len() >= max_items || size > max_size {
+      break;
+    }
+    kept.push(item);
+  }
+
+  // add_entry inserts at the front, so rebuild from oldest to newest
+  let mut trimmed = History::default();
+  for item in kept.into_iter().rev() {
+    trimmed.add_entry(item.clone());
+  }
+  trimmed
+}
+
+fn entry_size(entry: &Entry) -> usize {
+  let fields: usize = entry.fields.iter().map(|(k, v)| k.len() + v.len()).sum();
+  let attachments: usize = entry
+    .attachments
+    .iter()
+    .map(|(k, v)| k.len() + v.len())
+    .sum();
+  fields + attachments + entry.tags.iter().map(String::len).sum::<usize>()
+}
+
+/// Field level differences between two versions of an entry.
+pub fn diff_entries(old: &Entry, new: &Entry) -> Vec<KeyFieldChange> {
+  let mut names: Vec<&String> = old.fields.keys().chain(new.fields.keys()).collect();
+  names.sort();
+  names.dedup();
+
+  names
+    .into_iter()
+    .filter_map(|name| {
+      let old_value = old.get(name);
+      let new_value = new.get(name);
+      let kind = match (old_value, new_value) {
+        (None, Some(_)) => ChangeKind::Added,
+        (Some(_), None) => ChangeKind::Removed,
+        (Some(a), Some(b)) if a != b => ChangeKind::Changed,
+        _ => return None,
+      };
+      let protected = [old, new]
+        .iter()
+        .any(|e| matches!(e.fields.get(name), Some(Value::Protected(_))));
+      let reveal = |v: Option<&str>| v.filter(|_| !protected).map(str::to_string);
+
+      Some(KeyFieldChange {
+        field: name.clone(),
+        kind,
+        old: reveal(old_value),
+        new: reveal(new_value),
+        protected,
+      })
+    })
+    .collect()
+}
+
+pub fn get_entry_history(
+  db: &Database,
+  name: &str,
+  diff: bool,
+) -> Result<Vec<KeyHistoryItem>> {
+  let entry = find_entry(db, name)?;
+  let versions = match &entry.history {
+    Some(history) => history.get_entries(),
+    None => return Ok(Vec::new()),
+  };
+
+  Ok(
+    versions
+      .iter()
+      .enumerate()
+      .map(|(i, version)| {
+        let newer = if i == 0 { entry } else { &versions[i - 1] };
+        KeyHistoryItem {
+          index: i + 1,
+          title: version.get_title().unwrap_or_default().to_string(),
+          modified: version.times.last_modification,
+          changes: diff.then(|| diff_entries(version, newer)),
+        }
+      })
+      .collect(),
+  )
+}
+
+/// Replaces the entry with version `index` of its history (1 is the most recent
+/// previous version). The current state is kept in the history, so a restore
+/// can be undone as well.
+pub fn restore_entry(db: &mut Database, name: &str, index: usize) -> Result<()> {
+  let entry = find_entry_mut(&mut db.root, name)?;
+  let version = entry
+    .history
+    .as_ref()
+    .and_then(|h| h.get_entries().get(index.wrapping_sub(1)))
+    .cloned()
+    .ok_or(anyhow!("Entry has no history item {}", index))?;
+
+  backup_entry(entry, &db.meta);
+
+  let history = entry.history.take();
+  let mut times = version.times.clone();
+  times.last_modification = Some(Times::now());
+  *entry = Entry {
+    history,
+    times,
+    ..version
+  };
+
+  Ok(())
+}
@@ -0,0 +1,909 @@
+use std::{
+  collections::HashMap,
+  io::{Cursor, Read},
+};
+
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use keepass::{
+  db::{Attachment, Times},
+  DatabaseKey,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value as Json;
+use uuid::Uuid;
+
+use crate::{
+  db_from, ensure_group, recycle_bin, split_group_path, walk_entries, Database, Entry,
+  Group, Value,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ImportFormat {
+  /// Unencrypted Bitwarden JSON export
+  Bitwarden,
+  /// 1Password 1PUX export
+  #[value(name = "1password")]
+  OnePassword,
+  /// LastPass CSV export
+  Lastpass,
+  /// Chrome or other Chromium browser CSV export
+  Chrome,
+  /// KeePassXC CSV export
+  KeepassxcCsv,
+  /// Another KeePass database
+  Kdbx,
+}
+
+/// An entry read from an export, not yet added to a database.
+pub struct ImportedEntry {
+  /// Path of the group the entry belongs in, relative to the import group
+  pub group: Vec<String>,
+  pub entry: Entry,
+  /// Names of attachments the export refers to without containing them, like
+  /// Bitwarden JSON exports do.
+  pub missing_attachments: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ImportStatus {
+  Imported,
+  /// An entry with the same path and username already exists and was left alone
+  Exists,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct KeyImportItem {
+  pub path: String,
+  pub status: ImportStatus,
+  pub skipped_attachments: Vec<String>,
+}
+
+/// Reads all entries from an export. `key` is only needed for [ImportFormat::Kdbx].
+pub fn read_import(
+  format: ImportFormat,
+  data: Vec<u8>,
+  key: Option<DatabaseKey>,
+) -> Result<Vec<ImportedEntry>> {
+  match format {
+    ImportFormat::Bitwarden => read_bitwarden(&data),
+    ImportFormat::OnePassword => read_1pux(data),
+    ImportFormat::Lastpass => read_lastpass(&data),
+    ImportFormat::Chrome => read_chrome(&data),
+    ImportFormat::KeepassxcCsv => read_keepassxc_csv(&data),
+    ImportFormat::Kdbx => {
+      let key = key.ok_or(anyhow!("A key is needed to import a kdbx database"))?;
+      read_kdbx(db_from(data, key)?)
+    }
+  }
+}
+
+/// Adds the entries below the group at `group` ("" for the root group), creating
+/// groups as needed. Entries whose path and username already exist, in the
+/// database or earlier in the import, are skipped. Other accounts with the same
+/// title are added next to each other and can be told apart by their uuid. With
+/// `dry_run` the database is left untouched.
+///
+/// Attachments missing from the export fail the import, unless
+/// `skip_attachments` is given. They are then reported in the items.
+pub fn import_entries(
+  db: &mut Database,
+  entries: Vec<ImportedEntry>,
+  group: &str,
+  dry_run: bool,
+  skip_attachments: bool,
+) -> Result<Vec<KeyImportItem>> {
+  let missing: Vec<String> = entries
+    .iter()
+    .flat_map(|imported| {
+      let title = imported.entry.get_title().unwrap_or_default();
+      imported
+        .missing_attachments
+        .iter()
+        .map(move |name| format!("\"{}\" of \"{}\"", name, title))
+    })
+    .collect();
+  if !skip_attachments && !missing.is_empty() {
+    return Err(anyhow!(
+      "The export does not contain attachment {}, use --skip-attachments to import without them",
+      missing.join(", ")
+    ));
+  }
+
+  let prefix: Vec<String> = split_group_path(group)
+    .into_iter()
+    .map(str::to_string)
+    .collect();
+  let mut existing: Vec<(String, String)> = walk_entries(&db.root)
+    .into_iter()
+    .map(|(path, e)| (path, e.get_username().unwrap_or_default().to_string()))
+    .collect();
+
+  let mut items = Vec::new();
+  for imported in entries {
+    let parts: Vec<String> = prefix
+      .iter()
+      .chain(imported.group.iter())
+      .cloned()
+      .collect();
+    let title = imported.entry.get_title().unwrap_or_default().to_string();
+    let path = parts
+      .iter()
+      .map(String::as_str)
+      .chain([title.as_str()])
+      .collect::<Vec<&str>>()
+      .join("/");
+
+    let account = (
+      path,
+      imported
+        .entry
+        .get_username()
+        .unwrap_or_default()
+        .to_string(),
+    );
+    if existing.contains(&account) {
+      let (path, _) = account;
+      items.push(KeyImportItem {
+        path,
+        status: ImportStatus::Exists,
+        skipped_attachments: Vec::new(),
+      });
+      continue;
+    }
+
+    if !dry_run {
+      ensure_group(&mut db.root, &parts)
+        .entries
+        .push(imported.entry);
+    }
+    existing.push(account.clone());
+    let (path, _) = account;
+    items.push(KeyImportItem {
+      path,
+      status: ImportStatus::Imported,
+      skipped_attachments: imported.missing_attachments,
+    });
+  }
+
+  Ok(items)
+}
+
+/// Collects fields for a new entry. Empty values are left out, trailing
+/// whitespace is removed from values that are not protected.
+#[derive(Default)]
+struct EntryBuilder {
+  entry: Entry,
+  group: Vec<String>,
+  missing_attachments: Vec<String>,
+}
+
+impl EntryBuilder {
+  fn new(title: &str) -> Self {
+    let mut builder = EntryBuilder {
+      entry: Entry::new(),
+      ..Default::default()
+    };
+    builder.set("Title", Some(title), false);
+    builder
+  }
+
+  fn set(&mut self, field: &str, value: Option<&str>, protected: bool) -> &mut Self {
+    let value = match value.map(|v| if protected { v } else { v.trim_end() }) {
+      Some(value) if !value.is_empty() => value,
+      _ => return self,
+    };
+    // never overwrite a field, keep both values instead
+    let mut name = field.to_string();
+    let mut i = 2;
+    while self.entry.fields.contains_key(&name) {
+      name = format!("{} {}", field, i);
+      i += 1;
+    }
+    let value = match protected {
+      true => Value::protected(value),
+      false => Value::Unprotected(value.to_string()),
+    };
+    self.entry.fields.insert(name, value);
+    self
+  }
+
+  /// Adds an attachment, or records it as missing without `data`. Like fields,
+  /// attachments are never overwritten.
+  fn attach(&mut self, name: &str, data: Option<Vec<u8>>) -> &mut Self {
+    let data = match data {
+      Some(data) => data,
+      None => {
+        self.missing_attachments.push(name.to_string());
+        return self;
+      }
+    };
+    let mut unique = name.to_string();
+    let mut i = 2;
+    while self.entry.attachments.contains_key(&unique) {
+      unique = format!("{} {}", name, i);
+      i += 1;
+    }
+    let data = Value::protected(data);
+    self.entry.attachments.insert(unique, Attachment { data });
+    self
+  }
+
+  fn group(&mut self, path: &str, separators: &[char]) -> &mut Self {
+    self.group = path
+      .split(separators)
+      .map(str::trim)
+      .filter(|p| !p.is_empty())
+      .map(str::to_string)
+      .collect();
+    self
+  }
+
+  fn build(self) -> ImportedEntry {
+    ImportedEntry {
+      group: self.group,
+      entry: self.entry,
+      missing_attachments: self.missing_attachments,
+    }
+  }
+}
+
+/// Reads a csv file into rows keyed by the lowercase column names.
+fn read_csv(data: &[u8]) -> Result<Vec<HashMap<String, String>>> {
+  let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(data);
+  let headers: Vec<String> = reader
+    .headers()?
+    .iter()
+    .map(|h| h.trim().trim_start_matches('\u{feff}').to_lowercase())
+    .collect();
+
+  let mut rows = Vec::new();
+  for record in reader.records() {
+    let record = record?;
+    rows.push(
+      headers
+        .iter()
+        .cloned()
+        .zip(record.iter().map(str::to_string))
+        .collect(),
+    );
+  }
+  Ok(rows)
+}
+
+fn require_columns(rows: &[HashMap<String, String>], columns: &[&str]) -> Result<()> {
+  match rows.first() {
+    Some(row) => match columns.iter().find(|c| !row.contains_key(**c)) {
+      Some(column) => Err(anyhow!("Column \"{}\" is missing", column)),
+      None => Ok(()),
+    },
+    None => Ok(()),
+  }
+}
+
+fn title_from_url(url: Option<&str>) -> Option<String> {
+  url::Url::parse(url?)
+    .ok()?
+    .host_str()
+    .map(|host| host.trim_start_matches("www.").to_string())
+}
+
+fn read_chrome(data: &[u8]) -> Result<Vec<ImportedEntry>> {
+  let rows = read_csv(data)?;
+  require_columns(&rows, &["url", "username", "password"])?;
+
+  Ok(
+    rows
+      .iter()
+      .map(|row| {
+        let get = |c: &str| row.get(c).map(String::as_str);
+        let title = get("name")
+          .filter(|n| !n.is_empty())
+          .map(str::to_string)
+          .or_else(|| title_from_url(get("url")))
+          .unwrap_or_default();
+        let mut builder = EntryBuilder::new(&title);
+        builder
+          .set("UserName", get("username"), false)
+          .set("Password", get("password"), true)
+          .set("URL", get("url"), false)
+          .set("Notes", get("note"), false);
+        builder.build()
+      })
+      .collect(),
+  )
+}
+
+fn read_lastpass(data: &[u8]) -> Result<Vec<ImportedEntry>> {
+  let rows = read_csv(data)?;
+  require_columns(&rows, &["url", "username", "password", "name"])?;
+
+  Ok(
+    rows
+      .iter()
+      .map(|row| {
+        let get = |c: &str| row.get(c).map(String::as_str);
+        // secure notes have the placeholder url "http://sn"
+        let url = get("url").filter(|u| *u != "http://sn");
+        let mut builder = EntryBuilder::new(get("name").unwrap_or_default());
+        builder
+          .group(get("grouping").unwrap_or_default(), &['\\', '/'])
+          .set("UserName", get("username"), false)
+          .set("Password", get("password"), true)
+          .set("URL", url, false)
+          .set("otp", get("totp"), true)
+          .set("Notes", get("extra"), false);
+        builder.build()
+      })
+      .collect(),
+  )
+}
+
+fn read_keepassxc_csv(data: &[u8]) -> Result<Vec<ImportedEntry>> {
+  let rows = read_csv(data)?;
+  require_columns(&rows, &["group", "title", "username", "password"])?;
+
+  Ok(
+    rows
+      .iter()
+      .map(|row| {
+        let get = |c: &str| row.get(c).map(String::as_str);
+        let mut builder = EntryBuilder::new(get("title").unwrap_or_default());
+        // the first group is the root group of the exported database
+        let group = get("group")
+          .unwrap_or_default()
+          .split_once('/')
+          .map_or("", |(_, rest)| rest);
+        builder
+          .group(group, &['/'])
+          .set("UserName", get("username"), false)
+          .set("Password", get("password"), true)
+          .set("URL", get("url"), false)
+          .set("otp", get("totp"), true)
+          .set("Notes", get("notes"), false);
+        builder.build()
+      })
+      .collect(),
+  )
+}
+
+fn json_str<'a>(value: &'a Json, key: &str) -> Option<&'a str> {
+  value.get(key).and_then(Json::as_str)
+}
+
+/// Adds every scalar value of a json object as a field, e.g. the card and
+/// identity details of Bitwarden items.
+fn set_object_fields(
+  builder: &mut EntryBuilder,
+  object: Option<&Json>,
+  protected: &[&str],
+) {
+  if let Some(Json::Object(map)) = object {
+    for (name, value) in map {
+      let value = match value {
+        Json::String(s) => s.clone(),
+        Json::Number(n) => n.to_string(),
+        Json::Bool(b) => b.to_string(),
+        _ => continue,
+      };
+      builder.set(name, Some(&value), protected.contains(&name.as_str()));
+    }
+  }
+}
+
+fn read_bitwarden(data: &[u8]) -> Result<Vec<ImportedEntry>> {
+  let export: Json = serde_json::from_slice(data)?;
+  if export.get("encrypted").and_then(Json::as_bool) == Some(true) {
+    return Err(anyhow!(
+      "Encrypted Bitwarden exports are not supported, export as unencrypted json"
+    ));
+  }
+
+  let folders: HashMap<&str, &str> = export
+    .get("folders")
+    .and_then(Json::as_array)
+    .into_iter()
+    .flatten()
+    .filter_map(|f| Some((json_str(f, "id")?, json_str(f, "name")?)))
+    .collect();
+
+  let items = export
+    .get("items")
+    .and_then(Json::as_array)
+    .ok_or(anyhow!("Not a Bitwarden export, \"items\" is missing"))?;
+
+  Ok(
+    items
+      .iter()
+      .map(|item| {
+        let mut builder = EntryBuilder::new(json_str(item, "name").unwrap_or_default());
+        if let Some(folder) = json_str(item, "folderId").and_then(|id| folders.get(id)) {
+          builder.group(folder, &['/']);
+        }
+
+        if let Some(login) = item.get("login") {
+          builder
+            .set("UserName", json_str(login, "username"), false)
+            .set("Password", json_str(login, "password"), true)
+            .set("otp", json_str(login, "totp"), true);
+          for uri in login
+            .get("uris")
+            .and_then(Json::as_array)
+            .into_iter()
+            .flatten()
+          {
+            builder.set("URL", json_str(uri, "uri"), false);
+          }
+        }
+        builder.set("Notes", json_str(item, "notes"), false);
+        set_object_fields(&mut builder, item.get("card"), &["number", "code"]);
+        set_object_fields(
+          &mut builder,
+          item.get("identity"),
+          &["ssn", "passportNumber", "licenseNumber"],
+        );
+
+        for field in item
+          .get("fields")
+          .and_then(Json::as_array)
+          .into_iter()
+          .flatten()
+        {
+          // type 1 is a hidden field
+          let hidden = field.get("type").and_then(Json::as_u64) == Some(1);
+          let name = json_str(field, "name").unwrap_or("Field");
+          let value = match field.get("value") {
+            Some(Json::String(s)) => Some(s.clone()),
+            Some(Json::Bool(b)) => Some(b.to_string()),
+            _ => None,
+          };
+          builder.set(name, value.as_deref(), hidden);
+        }
+
+        // unencrypted exports only name the attachments
+        for attachment in item
+          .get("attachments")
+          .and_then(Json::as_array)
+          .into_iter()
+          .flatten()
+        {
+          if let Some(name) = json_str(attachment, "fileName") {
+            builder.attach(name, None);
+          }
+        }
+
+        builder.build()
+      })
+      .collect(),
+  )
+}
+
+/// Value of a 1Password section field, which is an object with a single key
+/// naming its type, like `{ "concealed": "..." }`.
+fn read_1pux_value(value: &Json) -> Option<(&str, String)> {
+  let (kind, value) = value.as_object()?.iter().next()?;
+  let value = match value {
+    Json::String(s) => s.clone(),
+    Json::Number(n) => n.to_string(),
+    Json::Bool(b) => b.to_string(),
+    // e.g. emails: { "email_address": "..", "provider": null }
+    Json::Object(map) => map.values().find_map(Json::as_str)?.to_string(),
+    _ => return None,
+  };
+  Some((kind.as_str(), value))
+}
+
+fn read_1pux(data: Vec<u8>) -> Result<Vec<ImportedEntry>> {
+  let mut archive = zip::ZipArchive::new(Cursor::new(data))?;
+  let mut export = String::new();
+  archive
+    .by_name("export.data")
+    .map_err(|_| anyhow!("Not a 1PUX export, \"export.data\" is missing"))?
+    .read_to_string(&mut export)?;
+  let export: Json = serde_json::from_str(&export)?;
+
+  // attachments are stored as "files/<documentId>__<fileName>"
+  let mut files = HashMap::new();
+  for i in 0..archive.len() {
+    let mut file = archive.by_index(i)?;
+    let id = match file.name().strip_prefix("files/") {
+      Some(name) => name.split("__").next().unwrap_or(name).to_string(),
+      None => continue,
+    };
+    let mut data = Vec::new();
+    file.read_to_end(&mut data)?;
+    files.insert(id, data);
+  }
+
+  let mut entries = Vec::new();
+  let accounts = export.get("accounts").and_then(Json::as_array);
+  for account in accounts.into_iter().flatten() {
+    let vaults = account.get("vaults").and_then(Json::as_array);
+    for vault in vaults.into_iter().flatten() {
+      let vault_name = vault
+        .get("attrs")
+        .and_then(|a| json_str(a, "name"))
+        .unwrap_or_default();
+
+      let items = vault.get("items").and_then(Json::as_array);
+      for item in items.into_iter().flatten() {
+        // archived and trashed items stay behind
+        if json_str(item, "state").is_some_and(|s| s != "active") {
+          continue;
+        }
+        entries.push(read_1pux_item(item, vault_name, &mut files));
+      }
+    }
+  }
+  Ok(entries)
+}
+
+/// Adds a 1Password file, `{ "fileName": "..", "documentId": ".." }`, as
+/// attachment.
+fn attach_1pux_file(
+  builder: &mut EntryBuilder,
+  file: &Json,
+  files: &mut HashMap<String, Vec<u8>>,
+) {
+  if let Some(name) = json_str(file, "fileName") {
+    let data = json_str(file, "documentId").and_then(|id| files.remove(id));
+    builder.attach(name, data);
+  }
+}
+
+fn read_1pux_item(
+  item: &Json,
+  vault: &str,
+  files: &mut HashMap<String, Vec<u8>>,
+) -> ImportedEntry {
+  let overview = item.get("overview").unwrap_or(&Json::Null);
+  let details = item.get("details").unwrap_or(&Json::Null);
+
+  let mut builder = EntryBuilder::new(json_str(overview, "title").unwrap_or_default());
+  builder.group(vault, &['/']);
+
+  let login_fields = details.get("loginFields").and_then(Json::as_array);
+  for field in login_fields.into_iter().flatten() {
+    match json_str(field, "designation") {
+      Some("username") => builder.set("UserName", json_str(field, "value"), false),
+      Some("password") => builder.set("Password", json_str(field, "value"), true),
+      _ => builder.set(
+        json_str(field, "name").unwrap_or("Field"),
+        json_str(field, "value"),
+        json_str(field, "fieldType") == Some("P"),
+      ),
+    };
+  }
+  builder
+    .set("Password", json_str(details, "password"), true)
+    .set("URL", json_str(overview, "url"), false)
+    .set("Notes", json_str(details, "notesPlain"), false);
+
+  let sections = details.get("sections").and_then(Json::as_array);
+  for field in sections
+    .into_iter()
+    .flatten()
+    .filter_map(|s| s.get("fields").and_then(Json::as_array))
+    .flatten()
+  {
+    if let Some(file) = field.get("value").and_then(|v| v.get("file")) {
+      attach_1pux_file(&mut builder, file, files);
+      continue;
+    }
+    let (kind, value) = match field.get("value").and_then(read_1pux_value) {
+      Some(value) => value,
+      None => continue,
+    };
+    match kind {
+      "totp" => builder.set("otp", Some(&value), true),
+      _ => builder.set(
+        json_str(field, "title")
+          .filter(|t| !t.is_empty())
+          .unwrap_or(kind),
+        Some(&value),
+        kind == "concealed",
+      ),
+    };
+  }
+
+  builder.entry.tags = overview
+    .get("tags")
+    .and_then(Json::as_array)
+    .into_iter()
+    .flatten()
+    .filter_map(|t| t.as_str().map(str::to_string))
+    .collect();
+
+  if let Some(document) = details.get("documentAttributes") {
+    attach_1pux_file(&mut builder, document, files);
+  }
+
+  builder.build()
+}
+
+/// Copies all entries of another database outside its recycle bin, with their
+/// history, under new uuids.
+fn read_kdbx(source: Database) -> Result<Vec<ImportedEntry>> {
+  let bin = recycle_bin(&source).map(|g| g.uuid);
+  let mut entries = Vec::new();
+  collect_kdbx(&source.root, &mut Vec::new(), bin.as_ref(), &mut entries);
+  Ok(entries)
+}
+
+fn collect_kdbx(
+  group: &Group,
+  path: &mut Vec<String>,
+  skip: Option<&Uuid>,
+  entries: &mut Vec<ImportedEntry>,
+) {
+  for e in group.entries.iter() {
+    let mut entry = e.clone();
+    entry.uuid = Uuid::new_v4();
+    entry.times.location_changed = Some(Times::now());
+    entries.push(ImportedEntry {
+      group: path.clone(),
+      entry,
+      missing_attachments: Vec::new(),
+    });
+  }
+  for g in group.groups.iter().filter(|g| Some(&g.uuid) != skip) {
+    path.push(g.name.clone());
+    collect_kdbx(g, path, skip, entries);
+    path.pop();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::io::Write;
+
+  use keepass::config::DatabaseConfig;
+  use serde_json::json;
+
+  use super::*;
+
+  fn field<'a>(imported: &'a ImportedEntry, name: &str) -> Option<&'a str> {
+    imported.entry.get(name)
+  }
+
+  fn is_protected(imported: &ImportedEntry, name: &str) -> bool {
+    matches!(imported.entry.fields.get(name), Some(Value::Protected(_)))
+  }
+
+  #[test]
+  fn chrome_titles_default_to_the_host() {
+    let csv = "name,url,username,password,note\n\
+               ,https://www.example.com/login,alice ,pw one ,\n\
+               Work,https://example.org,bob,pw,a note\n";
+    let entries = read_chrome(csv.as_bytes()).unwrap();
+    assert_eq!(entries.len(), 2);
+
+    let alice = &entries[0];
+    assert_eq!(alice.entry.get_title(), Some("example.com"));
+    assert_eq!(field(alice, "UserName"), Some("alice"));
+    assert_eq!(field(alice, "Password"), Some("pw one "));
+    assert!(is_protected(alice, "Password"));
+    assert_eq!(field(alice, "Notes"), None);
+
+    assert_eq!(entries[1].entry.get_title(), Some("Work"));
+    assert_eq!(field(&entries[1], "Notes"), Some("a note"));
+  }
+
+  #[test]
+  fn csv_without_required_columns_fails() {
+    let err = read_chrome("name,url\nx,y\n".as_bytes()).err().unwrap();
+    assert_eq!(err.to_string(), "Column \"username\" is missing");
+  }
+
+  #[test]
+  fn lastpass_groups_and_secure_notes() {
+    let csv = "url,username,password,totp,extra,name,grouping,fav\n\
+               https://mail.com,tim,secret,JBSWY3DP,,Mail,Personal\\Web,0\n\
+               http://sn,,,,the note,Note,,0\n";
+    let entries = read_lastpass(csv.as_bytes()).unwrap();
+
+    assert_eq!(entries[0].group, vec!["Personal", "Web"]);
+    assert_eq!(field(&entries[0], "otp"), Some("JBSWY3DP"));
+    assert!(is_protected(&entries[0], "otp"));
+    assert_eq!(field(&entries[0], "URL"), Some("https://mail.com"));
+
+    assert!(entries[1].group.is_empty());
+    assert_eq!(field(&entries[1], "URL"), None);
+    assert_eq!(field(&entries[1], "Notes"), Some("the note"));
+  }
+
+  #[test]
+  fn keepassxc_csv_leaves_out_the_root_group() {
+    let csv =
+      "\"Group\",\"Title\",\"Username\",\"Password\",\"URL\",\"Notes\",\"TOTP\"\n\
+               \"Root/Prod/DB\",\"db\",\"admin\",\"pw\",\"\",\"\",\"\"\n\
+               \"Root\",\"mail\",\"tim\",\"pw\",\"\",\"\",\"\"\n";
+    let entries = read_keepassxc_csv(csv.as_bytes()).unwrap();
+    assert_eq!(entries[0].group, vec!["Prod", "DB"]);
+    assert_eq!(entries[0].entry.get_title(), Some("db"));
+    assert!(entries[1].group.is_empty());
+  }
+
+  #[test]
+  fn bitwarden_items() {
+    let export = json!({
+      "encrypted": false,
+      "folders": [{ "id": "f1", "name": "Work/Servers" }],
+      "items": [{
+        "name": "server",
+        "folderId": "f1",
+        "notes": "notes",
+        "login": {
+          "username": "root",
+          "password": "pw",
+          "totp": "otpauth://totp/x?secret=JBSWY3DP",
+          "uris": [{ "uri": "https://a.com" }, { "uri": "https://b.com" }]
+        },
+        "fields": [
+          { "name": "pin", "value": "1234", "type": 1 },
+          { "name": "region", "value": "eu", "type": 0 }
+        ],
+        "attachments": [{ "fileName": "id.pem" }]
+      }, {
+        "name": "card",
+        "card": { "cardholderName": "Tim", "number": "4111", "code": "123" }
+      }]
+    });
+    let entries = read_bitwarden(export.to_string().as_bytes()).unwrap();
+
+    let server = &entries[0];
+    assert_eq!(server.group, vec!["Work", "Servers"]);
+    assert_eq!(field(server, "UserName"), Some("root"));
+    assert_eq!(field(server, "URL"), Some("https://a.com"));
+    assert_eq!(field(server, "URL 2"), Some("https://b.com"));
+    assert!(is_protected(server, "pin"));
+    assert!(!is_protected(server, "region"));
+    assert!(is_protected(server, "otp"));
+    assert_eq!(server.missing_attachments, vec!["id.pem"]);
+
+    let card = &entries[1];
+    assert!(card.group.is_empty());
+    assert_eq!(field(card, "cardholderName"), Some("Tim"));
+    assert!(is_protected(card, "number"));
+    assert!(is_protected(card, "code"));
+  }
+
+  #[test]
+  fn encrypted_bitwarden_exports_are_refused() {
+    let export = json!({ "encrypted": true, "items": [] });
+    assert!(read_bitwarden(export.to_string().as_bytes()).is_err());
+  }
+
+  fn pux(export: &Json, files: &[(&str, &str)]) -> Vec<u8> {
+    let mut zip = zip::ZipWriter::new(Cursor::new(Vec::new()));
+    let options = zip::write::SimpleFileOptions::default();
+    zip.start_file("export.data", options).unwrap();
+    zip.write_all(export.to_string().as_bytes()).unwrap();
+    for (name, content) in files {
+      zip.start_file(format!("files/{}", name), options).unwrap();
+      zip.write_all(content.as_bytes()).unwrap();
+    }
+    zip.finish().unwrap().into_inner()
+  }
+
+  fn attachment<'a>(imported: &'a ImportedEntry, name: &str) -> Option<&'a [u8]> {
+    imported
+      .entry
+      .attachments
+      .get(name)
+      .map(|a| a.get().as_slice())
+  }
+
+  #[test]
+  fn onepassword_items() {
+    let export = json!({ "accounts": [{ "vaults": [{
+      "attrs": { "name": "Private" },
+      "items": [{
+        "state": "active",
+        "overview": { "title": "github", "url": "https://github.com", "tags": ["dev"] },
+        "details": {
+          "loginFields": [
+            { "designation": "username", "value": "tim" },
+            { "designation": "password", "value": "pw" }
+          ],
+          "notesPlain": "notes",
+          "sections": [{ "fields": [
+            { "title": "one-time password", "value": { "totp": "JBSWY3DP" } },
+            { "title": "recovery", "value": { "concealed": "codes" } },
+            { "title": "", "value": { "email": { "email_address": "t@x.com" } } },
+            { "title": "key", "value": { "file": { "fileName": "id.pem", "documentId": "d1" } } },
+            { "title": "gone", "value": { "file": { "fileName": "old.txt", "documentId": "d2" } } }
+          ]}]
+        }
+      }, {
+        "state": "active",
+        "overview": { "title": "passport" },
+        "details": { "documentAttributes": { "fileName": "scan.pdf", "documentId": "d3" } }
+      }, {
+        "state": "archived",
+        "overview": { "title": "old" },
+        "details": {}
+      }]
+    }]}]});
+    let files = [("d1__id.pem", "-----BEGIN"), ("d3__scan.pdf", "%PDF")];
+    let entries = read_1pux(pux(&export, &files)).unwrap();
+    assert_eq!(entries.len(), 2);
+
+    let github = &entries[0];
+    assert_eq!(github.group, vec!["Private"]);
+    assert_eq!(github.entry.get_title(), Some("github"));
+    assert_eq!(field(github, "UserName"), Some("tim"));
+    assert!(is_protected(github, "Password"));
+    assert_eq!(field(github, "URL"), Some("https://github.com"));
+    assert_eq!(field(github, "otp"), Some("JBSWY3DP"));
+    assert!(is_protected(github, "recovery"));
+    assert_eq!(field(github, "email"), Some("t@x.com"));
+    assert_eq!(github.entry.tags, vec!["dev"]);
+    assert_eq!(attachment(github, "id.pem"), Some("-----BEGIN".as_bytes()));
+    assert_eq!(github.missing_attachments, vec!["old.txt"]);
+
+    assert_eq!(attachment(&entries[1], "scan.pdf"), Some("%PDF".as_bytes()));
+  }
+
+  #[test]
+  fn import_skips_existing_accounts_only() {
+    let csv = "name,url,username,password\n\
+               ,https://example.com,alice,a\n\
+               ,https://example.com,bob,b\n\
+               ,https://example.com,alice,a\n";
+    let mut db = Database::new(DatabaseConfig::default());
+
+    let items = import_entries(
+      &mut db,
+      read_chrome(csv.as_bytes()).unwrap(),
+      "Web",
+      false,
+      false,
+    )
+    .unwrap();
+    let status: Vec<_> = items.iter().map(|i| &i.status).collect();
+    assert_eq!(
+      status,
+      vec![
+        &ImportStatus::Imported,
+        &ImportStatus::Imported,
+        &ImportStatus::Exists
+      ]
+    );
+    assert!(items.iter().all(|i| i.path == "Web/example.com"));
+    assert_eq!(walk_entries(&db.root).len(), 2);
+
+    let items = import_entries(
+      &mut db,
+      read_chrome(csv.as_bytes()).unwrap(),
+      "Web",
+      true,
+      false,
+    )
+    .unwrap();
+    assert!(items.iter().all(|i| i.status == ImportStatus::Exists));
+  }
+
+  #[test]
+  fn missing_attachments_need_skip_attachments() {
+    let export = json!({
+      "encrypted": false,
+      "items": [{ "name": "server", "attachments": [{ "fileName": "id.pem" }] }]
+    });
+    let entries = || read_bitwarden(export.to_string().as_bytes()).unwrap();
+    let mut db = Database::new(DatabaseConfig::default());
+
+    let err = import_entries(&mut db, entries(), "", false, false).unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "The export does not contain attachment \"id.pem\" of \"server\", \
+       use --skip-attachments to import without them"
+    );
+    assert!(walk_entries(&db.root).is_empty());
+
+    let items = import_entries(&mut db, entries(), "", false, true).unwrap();
+    assert_eq!(items[0].skipped_attachments, vec!["id.pem"]);
+    assert_eq!(walk_entries(&db.root).len(), 1);
+  }
+}
@@ -1,11 +1,17 @@
-use std::{fs, io::Cursor};
+use std::{collections::BTreeMap, fmt, io::Cursor, str::FromStr};
 
 use anyhow::{anyhow, Result};
+use chrono::NaiveDateTime;
 pub use keepass::{Database, DatabaseKey};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use rand::{rngs::OsRng, seq::SliceRandom, Rng};
 use serde::{Deserialize, Serialize};
 use totp_rs::{Algorithm, Secret, TOTP};
+use uuid::Uuid;
 
-pub use keepass::db::{Entry, Node, NodeRef, NodeRefMut, Value};
+use crate::{backup_entry, entry_expiry, recycle_bin};
+
+pub use keepass::db::{Attachment, Entry, Group, Value};
 
 pub static PASSWORD_CHARSET: &str =
   "ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz\
@@ -15,13 +21,235 @@ pub fn generate_password(length: &usize) -> String {
   random_string::generate(*length, PASSWORD_CHARSET)
 }
 
+/// EFF large wordlist (7776 words), see https://www.eff.org/dice
+pub static EFF_LARGE_WORDLIST: &str = include_str!("../assets/eff_large_wordlist.txt");
+
+#[derive(Debug, Clone)]
+pub struct PassphraseOptions {
+  pub words: usize,
+  pub separator: String,
+  pub capitalize: bool,
+  pub number: bool,
+}
+
+impl Default for PassphraseOptions {
+  fn default() -> Self {
+    Self {
+      words: 6,
+      separator: "-".to_string(),
+      capitalize: false,
+      number: false,
+    }
+  }
+}
+
+/// Parses a wordlist with one word per line. Lines in the diceware format
+/// ("11111\tabacus") are reduced to the word itself.
+pub fn parse_wordlist(source: &str) -> Vec<&str> {
+  source
+    .lines()
+    .filter_map(|line| line.split_whitespace().last())
+    .collect()
+}
+
+pub fn generate_passphrase(
+  options: &PassphraseOptions,
+  wordlist: Option<&str>,
+) -> Result<String> {
+  let words = parse_wordlist(wordlist.unwrap_or(EFF_LARGE_WORDLIST));
+
+  if words.is_empty() {
+    return Err(anyhow!("Wordlist is empty"));
+  }
+  if options.words == 0 {
+    return Err(anyhow!("Passphrase needs at least one word"));
+  }
+
+  let mut rng = OsRng;
+  let mut phrase: Vec<String> = (0..options.words)
+    .map(|_| {
+      let word = words.choose(&mut rng).unwrap();
+      if options.capitalize {
+        capitalize(word)
+      } else {
+        word.to_string()
+      }
+    })
+    .collect();
+
+  if options.number {
+    let index = rng.gen_range(0..phrase.len());
+    phrase[index].push_str(&rng.gen_range(0..10).to_string());
+  }
+
+  Ok(phrase.join(&options.separator))
+}
+
+fn capitalize(word: &str) -> String {
+  let mut chars = word.chars();
+  match chars.next() {
+    Some(first) => first.to_uppercase().chain(chars).collect(),
+    None => String::new(),
+  }
+}
+
+/// How a secret gets generated, parsed from "chars[:LENGTH]" or "words[:COUNT]".
+/// A bare number is read as a character length.
+#[derive(Debug, Clone)]
+pub enum PasswordPolicy {
+  Characters(usize),
+  Passphrase(PassphraseOptions),
+}
+
+impl Default for PasswordPolicy {
+  fn default() -> Self {
+    PasswordPolicy::Characters(18)
+  }
+}
+
+impl FromStr for PasswordPolicy {
+  type Err = anyhow::Error;
+
+  fn from_str(s: &str) -> Result<Self> {
+    let (kind, size) = match s.split_once(':') {
+      Some((kind, size)) => (kind, Some(size)),
+      None if s.chars().all(|c| c.is_ascii_digit()) => ("chars", Some(s)),
+      None => (s, None),
+    };
+    let size = size
+      .map(|size| size.parse::<usize>())
+      .transpose()
+      .map_err(|_| anyhow!("Invalid size in password policy \"{}\"", s))?;
+
+    match kind {
+      "chars" => Ok(PasswordPolicy::Characters(size.unwrap_or(18))),
+      "words" => {
+        let mut options = PassphraseOptions::default();
+        options.words = size.unwrap_or(options.words);
+        Ok(PasswordPolicy::Passphrase(options))
+      }
+      _ => Err(anyhow!("Unknown password policy \"{}\"", s)),
+    }
+  }
+}
+
+impl PasswordPolicy {
+  pub fn generate(&self) -> Result<String> {
+    match self {
+      PasswordPolicy::Characters(length) => Ok(generate_password(length)),
+      PasswordPolicy::Passphrase(options) => generate_passphrase(options, None),
+    }
+  }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct KeyEntry {
   uuid: String,
+  path: String,
   title: String,
   user: Option<String>,
   website: Option<String>,
   has_otp: bool,
+  tags: Vec<String>,
+  expiry_time: Option<NaiveDateTime>,
+  /// Name of the vault, when entries of several vaults are listed together
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  vault: Option<String>,
+}
+
+impl KeyEntry {
+  pub fn new(path: &str, e: &Entry) -> Self {
+    KeyEntry {
+      uuid: e.uuid.to_string(),
+      path: path.to_string(),
+      title: e.get_title().unwrap_or_default().to_string(),
+      user: e.get_username().map(str::to_string),
+      website: e.get_url().map(str::to_string),
+      has_otp: e.fields.contains_key("otp"),
+      tags: e.tags.clone(),
+      expiry_time: entry_expiry(e),
+      vault: None,
+    }
+  }
+
+  pub fn in_vault(mut self, vault: Option<&str>) -> Self {
+    self.vault = vault.map(str::to_string);
+    self
+  }
+}
+
+/// Everything known about an entry. Protected values are masked unless revealed.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct KeyEntryDetail {
+  pub uuid: String,
+  pub path: String,
+  pub title: String,
+  pub user: Option<String>,
+  pub website: Option<String>,
+  pub has_otp: bool,
+  pub fields: BTreeMap<String, KeyField>,
+  pub tags: Vec<String>,
+  pub notes: Option<String>,
+  pub icon_id: Option<usize>,
+  pub expires: bool,
+  pub expiry_time: Option<NaiveDateTime>,
+  pub created: Option<NaiveDateTime>,
+  pub modified: Option<NaiveDateTime>,
+  pub accessed: Option<NaiveDateTime>,
+  pub history_items: usize,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct KeyField {
+  /// None when the value is protected and was not revealed
+  pub value: Option<String>,
+  pub protected: bool,
+}
+
+impl KeyEntryDetail {
+  pub fn new(path: &str, e: &Entry, reveal: bool) -> Self {
+    let fields = e
+      .fields
+      .iter()
+      .filter_map(|(k, v)| {
+        let protected = matches!(v, Value::Protected(_));
+        let value = e.get(k)?;
+        let field = KeyField {
+          value: (reveal || !protected).then(|| value.to_string()),
+          protected,
+        };
+        Some((k.clone(), field))
+      })
+      .collect();
+
+    KeyEntryDetail {
+      uuid: e.uuid.to_string(),
+      path: path.to_string(),
+      title: e.get_title().unwrap_or_default().to_string(),
+      user: e.get_username().map(str::to_string),
+      website: e.get_url().map(str::to_string),
+      has_otp: e.fields.contains_key("otp"),
+      fields,
+      tags: e.tags.clone(),
+      notes: e.get("Notes").map(str::to_string),
+      icon_id: e.icon_id,
+      expires: e.times.expires.unwrap_or(false),
+      expiry_time: entry_expiry(e),
+      created: e.times.creation,
+      modified: e.times.last_modification,
+      accessed: e.times.last_access,
+      history_items: e.history.as_ref().map_or(0, |h| h.get_entries().len()),
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct KeyOtp {
+  pub code: String,
+  /// Seconds a code is valid for
+  pub period: u64,
+  /// Seconds until the current code expires
+  pub expires_in: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -41,160 +269,461 @@ pub enum KeyNode {
 }
 
 pub fn to_json(db: Database) -> Result<String> {
-  let entries = db.root.children;
-  let nodes: Vec<KeyNode> = entries
-    .iter()
-    .map(|n| {
-      let node = parse_node_tree(n);
-      node
-    })
-    .collect();
-  Ok(serde_json::to_string(&nodes)?)
+  Ok(serde_json::to_string(&parse_node_tree(&db.root))?)
 }
 
-pub fn delete_entry(db: &mut Database, name: &String) -> Result<()> {
-  let entry = db.root.get_mut(&[name]);
-  if entry.is_none() {
-    Err(anyhow::format_err!("Entry not found"))?;
-  }
-
-  let index = db
-    .root
-    .children
-    .iter()
-    .position(|n| {
-      if let Node::Entry(e) = n {
-        e.get_title().unwrap() == name
-      } else {
-        false
-      }
-    })
-    .unwrap();
-
-  db.root.children.remove(index);
-
+pub fn rename_entry(db: &mut Database, name: &str, new_name: &str) -> Result<()> {
+  let entry = find_entry_mut(&mut db.root, name)?;
+  backup_entry(entry, &db.meta);
+  entry.fields.insert(
+    "Title".to_string(),
+    Value::Unprotected(new_name.to_string()),
+  );
   Ok(())
 }
 
-pub fn rename_entry(db: &mut Database, name: &String, new_name: &String) -> Result<()> {
-  let entry = db.root.get_mut(&[name]);
-
-  if entry.is_none() {
-    Err(anyhow::format_err!("Entry not found"))?;
+pub fn add_tag(db: &mut Database, name: &str, tag: &str) -> Result<()> {
+  if tag.is_empty() || tag.contains([',', ';']) {
+    return Err(anyhow!("Invalid tag \"{}\"", tag));
   }
-
-  if let Some(NodeRefMut::Entry(entry)) = entry {
-    let title = entry.fields.get_mut("Title").unwrap();
-    *title = Value::Unprotected(new_name.clone());
+  let entry = find_entry_mut(&mut db.root, name)?;
+  if has_tag(entry, tag) {
     return Ok(());
   }
+  backup_entry(entry, &db.meta);
+  entry.tags.push(tag.to_string());
+  Ok(())
+}
 
-  Err(anyhow!("failed to rename entry"))
+pub fn remove_tag(db: &mut Database, name: &str, tag: &str) -> Result<()> {
+  let entry = find_entry_mut(&mut db.root, name)?;
+  if !has_tag(entry, tag) {
+    return Err(anyhow!("Entry has no tag \"{}\"", tag));
+  }
+  backup_entry(entry, &db.meta);
+  entry.tags.retain(|t| t != tag);
+  Ok(())
+}
+
+pub fn has_tag(entry: &Entry, tag: &str) -> bool {
+  entry.tags.iter().any(|t| t == tag)
 }
 
-pub fn set_entry(
+pub fn new_entry(
   db: &mut Database,
-  name: &String,
-  value: &String,
-  field: &String,
+  name: &str,
+  user: Option<&str>,
+  url: Option<&str>,
 ) -> Result<()> {
-  let entry = db.root.get_mut(&[name]);
+  if entry_exists(&db.root, name) {
+    return Err(anyhow!("Entry \"{}\" already exists", name));
+  }
+
+  let (group, title) = parent_group_mut(&mut db.root, name);
+  let mut new_entry = Entry::new();
+  new_entry
+    .fields
+    .insert("Title".to_string(), Value::Unprotected(title.to_string()));
+  if let Some(user) = user {
+    new_entry
+      .fields
+      .insert("UserName".to_string(), Value::Unprotected(user.to_string()));
+  }
+  if let Some(url) = url {
+    new_entry
+      .fields
+      .insert("URL".to_string(), Value::Unprotected(url.to_string()));
+  }
+  group.entries.push(new_entry);
 
-  if entry.is_none() {
+  Ok(())
+}
+
+pub fn set_entry(db: &mut Database, name: &str, value: &str, field: &str) -> Result<()> {
+  if !entry_exists(&db.root, name) {
     // add a new one
+    let (group, title) = parent_group_mut(&mut db.root, name);
     let mut new_entry = Entry::new();
     new_entry
       .fields
-      .insert("Title".to_string(), Value::Unprotected(name.to_string()));
+      .insert("Title".to_string(), Value::Unprotected(title.to_string()));
     new_entry
       .fields
-      .insert(field.to_string(), Value::Protected(value.as_bytes().into()));
-    db.root.add_child(new_entry);
+      .insert(field.to_string(), Value::protected(value));
+    group.entries.push(new_entry);
 
     return Ok(());
   }
 
-  if let Some(NodeRefMut::Entry(entry)) = entry {
-    let pw = entry.fields.get_mut(&field.to_string());
+  let entry = find_entry_mut(&mut db.root, name)?;
+  if entry.get(field) == Some(value) {
+    return Ok(());
+  }
 
-    if pw.is_none() {
-      entry
-        .fields
-        .insert(field.to_string(), Value::Protected(value.as_bytes().into()));
-    } else if let Some(pw) = pw {
-      *pw = Value::Protected(value.as_bytes().into());
-    }
+  backup_entry(entry, &db.meta);
+  entry
+    .fields
+    .insert(field.to_string(), Value::protected(value));
 
-    return Ok(());
+  Ok(())
+}
+
+/// Splits "Group/Title" into the group the entry belongs to and its title.
+/// Names whose parent path is not an existing group stay a root level title.
+fn parent_group_mut<'a, 'b>(
+  root: &'a mut Group,
+  name: &'b str,
+) -> (&'a mut Group, &'b str) {
+  if let Some((parent, title)) = name.rsplit_once('/') {
+    let path: Vec<&str> = parent.split('/').collect();
+    if root.group_by_path(&path).is_some() {
+      return (root.group_by_path_mut(&path).unwrap(), title);
+    }
   }
+  (root, name)
+}
 
-  Err(anyhow!("failed to set value"))
+/// Collects every entry below `group` together with its path ("Group/Title").
+pub fn walk_entries(group: &Group) -> Vec<(String, &Entry)> {
+  let mut entries = Vec::new();
+  collect_entries(group, "", None, &mut entries);
+  entries
 }
 
-pub fn get_entry(db: &Database, name: &String, field: &String) -> Result<String> {
-  if let Some(NodeRef::Entry(e)) = db.root.get(&[name]) {
-    return Ok(e.get(field).unwrap().to_string());
+/// Like [walk_entries] over the whole database, but without the entries in the
+/// recycle bin.
+pub fn list_entries(db: &Database) -> Vec<(String, &Entry)> {
+  let bin = recycle_bin(db).map(|g| g.uuid);
+  let mut entries = Vec::new();
+  collect_entries(&db.root, "", bin.as_ref(), &mut entries);
+  entries
+}
+
+fn collect_entries<'a>(
+  group: &'a Group,
+  prefix: &str,
+  skip: Option<&Uuid>,
+  entries: &mut Vec<(String, &'a Entry)>,
+) {
+  for e in group.entries.iter() {
+    let path = format!("{}{}", prefix, e.get_title().unwrap_or_default());
+    entries.push((path, e));
+  }
+  for g in group.groups.iter().filter(|g| Some(&g.uuid) != skip) {
+    collect_entries(g, &format!("{}{}/", prefix, g.name), skip, entries)
   }
-  Err(anyhow!("Entry not found"))
 }
 
-pub fn get_entry_file(db: &Database, _name: &String, _file: &String) -> Result<String> {
-  for a in db.header_attachments.clone() {
-    let path =
-      String::from_utf8(a.content.clone()).expect("Our bytes should be valid utf8");
-    fs::write(path, a.content)?;
+/// Prefix to address an entry by its uuid instead of its path ("uuid:<uuid>").
+pub const UUID_PREFIX: &str = "uuid:";
+
+/// Finds an entry by its path through the group tree ("Group/Title") or by its
+/// uuid ("uuid:<uuid>"). Titles are not unique, so a path that matches more
+/// than one entry fails with the list of candidates.
+pub fn find_entry<'a>(db: &'a Database, name: &str) -> Result<&'a Entry> {
+  let uuid = resolve_entry(&db.root, name)?;
+  walk_entries(&db.root)
+    .into_iter()
+    .map(|(_, e)| e)
+    .find(|e| e.uuid == uuid)
+    .ok_or(anyhow!("Entry not found"))
+}
+
+/// Mutable counterpart of [find_entry]. Takes the root group so that the
+/// database meta data can still be read while the entry is borrowed.
+pub fn find_entry_mut<'a>(root: &'a mut Group, name: &str) -> Result<&'a mut Entry> {
+  let uuid = resolve_entry(root, name)?;
+  root
+    .entry_by_uuid_mut(uuid)
+    .ok_or(anyhow!("Entry not found"))
+}
+
+/// Whether `name` refers to any entry. Used to decide between updating and
+/// creating an entry, where an ambiguous name must not add yet another one.
+pub(crate) fn entry_exists(root: &Group, name: &str) -> bool {
+  name.starts_with(UUID_PREFIX) || walk_entries(root).iter().any(|(path, _)| path == name)
+}
+
+fn resolve_entry(root: &Group, name: &str) -> Result<Uuid> {
+  if let Some(uuid) = name.strip_prefix(UUID_PREFIX) {
+    let uuid = Uuid::parse_str(uuid).map_err(|_| anyhow!("Invalid uuid \"{}\"", uuid))?;
+    return match walk_entries(root).iter().any(|(_, e)| e.uuid == uuid) {
+      true => Ok(uuid),
+      false => Err(anyhow!("Entry not found")),
+    };
   }
-  Err(anyhow!("failed to get value"))
+
+  let candidates: Vec<(String, &Entry)> = walk_entries(root)
+    .into_iter()
+    .filter(|(path, _)| path == name)
+    .collect();
+
+  match candidates.as_slice() {
+    [] => Err(anyhow!("Entry not found")),
+    [(_, e)] => Ok(e.uuid),
+    _ => {
+      let candidates = candidates
+        .iter()
+        .map(|(path, e)| {
+          let username = e.get_username().unwrap_or_default();
+          (e.uuid, format!("{} ({})", path, username))
+        })
+        .collect();
+      Err(ambiguous_name(name, "entries", candidates))
+    }
+  }
+}
+
+/// Error for a name that matches several items, listing their uuids to use
+/// instead, each with a description of the item.
+pub(crate) fn ambiguous_name(
+  name: &str,
+  items: &str,
+  candidates: Vec<(Uuid, String)>,
+) -> anyhow::Error {
+  let list: Vec<String> = candidates
+    .iter()
+    .map(|(uuid, description)| format!("  {}{}  {}", UUID_PREFIX, uuid, description))
+    .collect();
+  anyhow!(
+    "\"{}\" matches {} {}, use one of:\n{}",
+    name,
+    candidates.len(),
+    items,
+    list.join("\n")
+  )
+}
+
+pub fn get_entry_detail(
+  db: &Database,
+  name: &str,
+  reveal: bool,
+) -> Result<KeyEntryDetail> {
+  let entry = find_entry(db, name)?;
+  let path = walk_entries(&db.root)
+    .into_iter()
+    .find(|(_, e)| e.uuid == entry.uuid)
+    .map(|(path, _)| path)
+    .unwrap_or(name.to_string());
+  Ok(KeyEntryDetail::new(&path, entry, reveal))
+}
+
+/// Case-insensitive search over path, username and url of all entries.
+pub fn search_entries<'a>(db: &'a Database, query: &str) -> Vec<(String, &'a Entry)> {
+  let query = query.to_lowercase();
+  list_entries(db)
+    .into_iter()
+    .filter(|(path, e)| {
+      [Some(path.as_str()), e.get_username(), e.get_url()]
+        .iter()
+        .flatten()
+        .any(|v| v.to_lowercase().contains(&query))
+    })
+    .collect()
 }
 
-pub fn get_entry_otp(db: &Database, name: &String, field: &String) -> Result<String> {
-  if let Some(NodeRef::Entry(e)) = db.root.get(&[name]) {
-    let password = e.get(field).unwrap().to_string();
-    return Ok(otp(password, None, None)?);
+pub fn get_entry(db: &Database, name: &str, field: &str) -> Result<String> {
+  let e = find_entry(db, name)?;
+  e.get(field)
+    .map(str::to_string)
+    .ok_or(anyhow!("Field \"{}\" not found", field))
+}
+
+/// Content of the attachment named `attachment` of an entry.
+pub fn get_entry_attachment<'a>(
+  db: &'a Database,
+  name: &str,
+  attachment: &str,
+) -> Result<&'a [u8]> {
+  find_entry(db, name)?
+    .attachments
+    .get(attachment)
+    .map(|a| a.get().as_slice())
+    .ok_or(anyhow!("Attachment \"{}\" not found", attachment))
+}
+
+pub fn get_entry_otp(db: &Database, name: &str, field: &str) -> Result<String> {
+  Ok(get_entry_otp_details(db, name, field)?.code)
+}
+
+pub fn get_entry_otp_details(db: &Database, name: &str, field: &str) -> Result<KeyOtp> {
+  if let Ok(Some(secret)) = find_entry(db, name).map(|e| e.get(field)) {
+    return otp_details(secret.to_string(), None, None);
   }
   Err(anyhow::format_err!("Entry not found or does not have otp"))
 }
 
-pub fn parse_node_tree(node: &Node) -> KeyNode {
-  match node {
-    Node::Group(g) => {
-      let entries: Vec<KeyNode> = g.children.iter().map(parse_node_tree).collect();
-      KeyNode::Group(KeyGroup {
-        uuid: g.uuid.to_string(),
-        title: g.name.clone(),
-        entries,
-      })
+/// Scheme of secret references, see [KeyReference].
+pub const REFERENCE_SCHEME: &str = "key://";
+
+/// Characters escaped in the segments of a reference
+const REFERENCE_ESCAPE: &AsciiSet = &CONTROLS
+  .add(b' ')
+  .add(b'"')
+  .add(b'#')
+  .add(b'%')
+  .add(b'/')
+  .add(b'?');
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ReferenceTarget {
+  Field(String),
+  /// The current code of the "otp" field
+  Otp,
+  Attachment(String),
+}
+
+/// A reference to a secret in the database:
+/// - `key://Group/Entry/Field` is the value of a field,
+/// - `key://Group/Entry?otp` is the current one time password,
+/// - `key://Group/Entry?attachment=file.pem` is an attachment, which must be text.
+///
+/// The entry can also be given as `uuid:<uuid>`. Segments are percent-encoded,
+/// so "My Entry" is written as `My%20Entry`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyReference {
+  pub entry: String,
+  pub target: ReferenceTarget,
+}
+
+impl KeyReference {
+  pub fn parse(uri: &str) -> Result<Self> {
+    let invalid = || {
+      anyhow!(
+        "Invalid reference \"{}\", use key://Group/Entry/Field or key://Group/Entry?otp",
+        uri
+      )
+    };
+    let decode = |s: &str| {
+      percent_decode_str(s)
+        .decode_utf8()
+        .map(|s| s.to_string())
+        .map_err(|_| invalid())
+    };
+
+    let rest = uri.strip_prefix(REFERENCE_SCHEME).ok_or_else(invalid)?;
+    let (path, query) = match rest.split_once('?') {
+      Some((path, query)) => (path, Some(query)),
+      None => (rest, None),
+    };
+    let segments = path
+      .split('/')
+      .map(decode)
+      .collect::<Result<Vec<String>>>()?;
+    if segments.iter().any(String::is_empty) {
+      return Err(invalid());
+    }
+
+    let (entry, target) = match query {
+      Some("otp") => (&segments[..], ReferenceTarget::Otp),
+      Some(query) => match query.strip_prefix("attachment=") {
+        Some(name) if !name.is_empty() => {
+          (&segments[..], ReferenceTarget::Attachment(decode(name)?))
+        }
+        _ => return Err(anyhow!("Unknown reference option \"{}\" in {}", query, uri)),
+      },
+      None => match segments.split_last() {
+        Some((field, entry)) => (entry, ReferenceTarget::Field(field.clone())),
+        None => return Err(invalid()),
+      },
+    };
+    if entry.is_empty() {
+      return Err(invalid());
+    }
+
+    Ok(KeyReference {
+      entry: entry.join("/"),
+      target,
+    })
+  }
+
+  pub fn resolve(&self, db: &Database) -> Result<String> {
+    match &self.target {
+      ReferenceTarget::Field(field) => get_entry(db, &self.entry, field),
+      ReferenceTarget::Otp => get_entry_otp(db, &self.entry, "otp"),
+      ReferenceTarget::Attachment(name) => {
+        let content = get_entry_attachment(db, &self.entry, name)?;
+        String::from_utf8(content.to_vec())
+          .map_err(|_| anyhow!("Attachment \"{}\" is not text", name))
+      }
+    }
+  }
+}
+
+impl fmt::Display for KeyReference {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let encode = |s: &str| utf8_percent_encode(s, REFERENCE_ESCAPE).to_string();
+    // the colon of "uuid:" is left as is
+    let entry: Vec<String> = self.entry.split('/').map(encode).collect();
+    write!(f, "{}{}", REFERENCE_SCHEME, entry.join("/"))?;
+    match &self.target {
+      ReferenceTarget::Field(field) => write!(f, "/{}", encode(field)),
+      ReferenceTarget::Otp => write!(f, "?otp"),
+      ReferenceTarget::Attachment(name) => write!(f, "?attachment={}", encode(name)),
     }
-    Node::Entry(e) => KeyNode::Entry(KeyEntry {
-      uuid: e.uuid.to_string(),
-      title: e.get_title().unwrap().to_string(),
-      user: e.get_username().map(str::to_string),
-      website: e.get_url().map(str::to_string),
-      has_otp: e.fields.contains_key("otp"),
-    }),
   }
 }
 
+/// Resolves a `key://` reference, see [KeyReference].
+pub fn resolve_reference(db: &Database, uri: &str) -> Result<String> {
+  KeyReference::parse(uri)?.resolve(db)
+}
+
+/// The groups and entries directly in `group`, with everything below them.
+pub fn parse_node_tree(group: &Group) -> Vec<KeyNode> {
+  parse_nodes(group, "")
+}
+
+fn parse_nodes(group: &Group, prefix: &str) -> Vec<KeyNode> {
+  let groups = group.groups.iter().map(|g| {
+    let prefix = format!("{}{}/", prefix, g.name);
+    KeyNode::Group(KeyGroup {
+      uuid: g.uuid.to_string(),
+      title: g.name.clone(),
+      entries: parse_nodes(g, &prefix),
+    })
+  });
+  let entries = group.entries.iter().map(|e| {
+    KeyNode::Entry(KeyEntry::new(
+      &format!("{}{}", prefix, e.get_title().unwrap_or_default()),
+      e,
+    ))
+  });
+  groups.chain(entries).collect()
+}
+
 pub fn otp(
   secret: String,
   issuer: Option<String>,
   account: Option<String>,
 ) -> Result<String> {
-  if secret.starts_with("otpauth:") {
-    let totp = TOTP::from_url_unchecked(secret).unwrap();
-    Ok(totp.generate_current()?)
+  Ok(otp_details(secret, issuer, account)?.code)
+}
+
+pub fn otp_details(
+  secret: String,
+  issuer: Option<String>,
+  account: Option<String>,
+) -> Result<KeyOtp> {
+  let totp = if secret.starts_with("otpauth:") {
+    TOTP::from_url_unchecked(secret)?
   } else {
-    let totp = TOTP::new_unchecked(
+    TOTP::new_unchecked(
       Algorithm::SHA1,
       6,
       1,
       30,
       Secret::Encoded(secret).to_bytes()?,
       issuer,
-      account.or(Some("".to_string())).unwrap(),
-    );
-    Ok(totp.generate_current()?)
-  }
+      account.unwrap_or_default(),
+    )
+  };
+
+  Ok(KeyOtp {
+    code: totp.generate_current()?,
+    period: totp.step,
+    expires_in: totp.ttl()?,
+  })
 }
 
 pub fn key_from(
@@ -219,3 +748,134 @@ pub fn db_from(source: Vec<u8>, key: DatabaseKey) -> Result<Database> {
   let mut cursor = Cursor::new(source);
   Ok(Database::open(&mut cursor, key.clone())?)
 }
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn reference(entry: &str, target: ReferenceTarget) -> KeyReference {
+    KeyReference {
+      entry: entry.to_string(),
+      target,
+    }
+  }
+
+  fn field(name: &str) -> ReferenceTarget {
+    ReferenceTarget::Field(name.to_string())
+  }
+
+  #[test]
+  fn parse_field_reference() {
+    assert_eq!(
+      KeyReference::parse("key://Group/Sub/Entry/Password").unwrap(),
+      reference("Group/Sub/Entry", field("Password"))
+    );
+    assert_eq!(
+      KeyReference::parse("key://github/UserName").unwrap(),
+      reference("github", field("UserName"))
+    );
+  }
+
+  #[test]
+  fn parse_otp_reference() {
+    assert_eq!(
+      KeyReference::parse("key://Group/Entry?otp").unwrap(),
+      reference("Group/Entry", ReferenceTarget::Otp)
+    );
+  }
+
+  #[test]
+  fn parse_decodes_segments() {
+    assert_eq!(
+      KeyReference::parse("key://My%20Group/a%2Fb/API%20Key").unwrap(),
+      reference("My Group/a/b", field("API Key"))
+    );
+  }
+
+  #[test]
+  fn parse_uuid_entry() {
+    let uuid = "0e6d3a6c-1d4b-4d4c-9a3e-6f2b7c1a9f00";
+    assert_eq!(
+      KeyReference::parse(&format!("key://uuid:{}/Password", uuid)).unwrap(),
+      reference(&format!("uuid:{}", uuid), field("Password"))
+    );
+    assert_eq!(
+      KeyReference::parse(&format!("key://uuid:{}?otp", uuid)).unwrap(),
+      reference(&format!("uuid:{}", uuid), ReferenceTarget::Otp)
+    );
+  }
+
+  #[test]
+  fn parse_rejects_invalid_references() {
+    for uri in [
+      "https://github/Password",
+      "key://",
+      "key://Password",
+      "key://Group//Password",
+      "key://Group/Entry/",
+      "key://?otp",
+      "key://Entry/%FF",
+    ] {
+      assert!(KeyReference::parse(uri).is_err(), "{} was accepted", uri);
+    }
+    let err = KeyReference::parse("key://Entry?totp").unwrap_err();
+    assert_eq!(
+      err.to_string(),
+      "Unknown reference option \"totp\" in key://Entry?totp"
+    );
+  }
+
+  #[test]
+  fn parse_attachment_reference() {
+    assert_eq!(
+      KeyReference::parse("key://Group/Entry?attachment=id%20rsa.pem").unwrap(),
+      reference(
+        "Group/Entry",
+        ReferenceTarget::Attachment("id rsa.pem".to_string())
+      )
+    );
+    assert!(KeyReference::parse("key://Entry?attachment=").is_err());
+  }
+
+  #[test]
+  fn resolve_attachment_reference() {
+    let mut db = Database::new(keepass::config::DatabaseConfig::default());
+    let mut entry = Entry::new();
+    entry
+      .fields
+      .insert("Title".to_string(), Value::unprotected("server"));
+    for (name, data) in [("id.pem", b"-----BEGIN".to_vec()), ("blob", vec![0xff])] {
+      let attachment = Attachment {
+        data: Value::protected(data),
+      };
+      entry.attachments.insert(name.to_string(), attachment);
+    }
+    db.root.entries.push(entry);
+
+    assert_eq!(
+      resolve_reference(&db, "key://server?attachment=id.pem").unwrap(),
+      "-----BEGIN"
+    );
+    for (uri, error) in [
+      (
+        "key://server?attachment=blob",
+        "Attachment \"blob\" is not text",
+      ),
+      ("key://server?attachment=x", "Attachment \"x\" not found"),
+    ] {
+      assert_eq!(resolve_reference(&db, uri).unwrap_err().to_string(), error);
+    }
+  }
+
+  #[test]
+  fn display_encodes_what_parse_decodes() {
+    for uri in [
+      "key://My%20Group/Entry%3F/API%20Key",
+      "key://Group/Entry?otp",
+      "key://Group/Entry?attachment=id%20rsa.pem",
+      "key://uuid:0e6d3a6c-1d4b-4d4c-9a3e-6f2b7c1a9f00/Password",
+    ] {
+      assert_eq!(KeyReference::parse(uri).unwrap().to_string(), uri);
+    }
+  }
+}
@@ -1,10 +1,50 @@
+mod credential;
+mod expiry;
+mod group;
+mod history;
 mod key;
+mod template;
+mod trash;
 
+pub use credential::*;
+pub use expiry::*;
+pub use group::*;
+pub use history::*;
 pub use key::*;
+pub use template::*;
+pub use trash::*;
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(feature = "cli")]
+pub mod audit;
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(feature = "cli")]
+pub mod config;
 
 #[cfg(not(target_arch = "wasm32"))]
 #[cfg(feature = "cli")]
 pub mod db;
 
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(feature = "cli")]
+pub mod export;
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(feature = "cli")]
+pub mod import;
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(feature = "cli")]
+pub mod output;
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(feature = "cli")]
+pub mod ssh;
+
+#[cfg(not(target_arch = "wasm32"))]
+#[cfg(feature = "cli")]
+pub mod tui;
+
 #[cfg(feature = "wasm")]
 pub mod wasm;
@@ -0,0 +1,200 @@
+use anyhow::{anyhow, Result};
+use clap::ValueEnum;
+use serde::Serialize;
+use serde_json::Value;
+
+/// Version of the machine readable output. Bump it whenever a field is renamed
+/// or removed; adding fields is not a breaking change.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Format {
+  #[default]
+  Text,
+  Json,
+  Yaml,
+  Toml,
+  Csv,
+  Env,
+}
+
+#[derive(Serialize)]
+struct Envelope<'a, T: Serialize> {
+  version: u32,
+  data: &'a T,
+}
+
+#[derive(Serialize)]
+struct ErrorEnvelope {
+  version: u32,
+  error: ErrorMessage,
+}
+
+#[derive(Serialize)]
+struct ErrorMessage {
+  message: String,
+}
+
+/// Renders `data` in one of the machine readable formats.
+///
+/// json, yaml and toml wrap the data as `{ version, data }`. csv and env are
+/// flat by nature and only contain the data itself.
+pub fn render<T: Serialize>(format: Format, data: &T) -> Result<String> {
+  let envelope = Envelope {
+    version: SCHEMA_VERSION,
+    data,
+  };
+
+  match format {
+    Format::Text => Err(anyhow!("Text output has no generic representation")),
+    Format::Json => Ok(serde_json::to_string_pretty(&envelope)?),
+    Format::Yaml => Ok(serde_yaml::to_string(&envelope)?),
+    Format::Toml => Ok(toml::to_string(&envelope)?),
+    Format::Csv => to_csv(&serde_json::to_value(data)?),
+    Format::Env => Ok(to_env(&serde_json::to_value(data)?)),
+  }
+}
+
+pub fn render_error(format: Format, error: &anyhow::Error) -> String {
+  let message = ErrorMessage {
+    message: error.to_string(),
+  };
+
+  match format {
+    Format::Text => error.to_string(),
+    Format::Csv => format!("error\n{}", csv_escape(&message.message)),
+    Format::Env => format!("KEY_ERROR={}", shell_quote(&message.message)),
+    _ => {
+      let envelope = ErrorEnvelope {
+        version: SCHEMA_VERSION,
+        error: message,
+      };
+      let rendered = match format {
+        Format::Yaml => serde_yaml::to_string(&envelope).ok(),
+        Format::Toml => toml::to_string(&envelope).ok(),
+        _ => serde_json::to_string_pretty(&envelope).ok(),
+      };
+      rendered.unwrap_or_else(|| error.to_string())
+    }
+  }
+}
+
+/// Flattens nested objects into dotted keys; arrays of scalars are joined by ";".
+fn flatten(prefix: &str, value: &Value, out: &mut Vec<(String, String)>) {
+  match value {
+    Value::Object(map) => {
+      for (key, value) in map {
+        flatten(&join_key(prefix, key), value, out);
+      }
+    }
+    Value::Array(items) if items.iter().all(|v| !v.is_object()) => {
+      let joined: Vec<String> = items.iter().map(scalar).collect();
+      out.push((prefix.to_string(), joined.join(";")));
+    }
+    Value::Array(items) => {
+      for (i, value) in items.iter().enumerate() {
+        flatten(&join_key(prefix, &i.to_string()), value, out);
+      }
+    }
+    _ => out.push((prefix.to_string(), scalar(value))),
+  }
+}
+
+fn join_key(prefix: &str, key: &str) -> String {
+  if prefix.is_empty() {
+    key.to_string()
+  } else {
+    format!("{}.{}", prefix, key)
+  }
+}
+
+fn scalar(value: &Value) -> String {
+  match value {
+    Value::Null => "".to_string(),
+    Value::String(s) => s.clone(),
+    other => other.to_string(),
+  }
+}
+
+fn to_csv(value: &Value) -> Result<String> {
+  let rows: Vec<Vec<(String, String)>> = match value {
+    Value::Array(items) => items
+      .iter()
+      .map(|item| {
+        let mut row = Vec::new();
+        flatten("", item, &mut row);
+        row
+      })
+      .collect(),
+    Value::Object(_) => {
+      let mut row = Vec::new();
+      flatten("", value, &mut row);
+      vec![row]
+    }
+    other => vec![vec![("value".to_string(), scalar(other))]],
+  };
+
+  let mut columns: Vec<String> = Vec::new();
+  for row in rows.iter() {
+    for (key, _) in row {
+      if !columns.contains(key) {
+        columns.push(key.clone());
+      }
+    }
+  }
+
+  let mut writer = csv::Writer::from_writer(Vec::new());
+  writer.write_record(&columns)?;
+  for row in rows.iter() {
+    let record = columns.iter().map(|column| {
+      row
+        .iter()
+        .find(|(key, _)| key == column)
+        .map(|(_, value)| value.as_str())
+        .unwrap_or("")
+    });
+    writer.write_record(record)?;
+  }
+
+  Ok(
+    String::from_utf8(writer.into_inner()?)?
+      .trim_end()
+      .to_string(),
+  )
+}
+
+fn csv_escape(value: &str) -> String {
+  format!("\"{}\"", value.replace('"', "\"\""))
+}
+
+fn to_env(value: &Value) -> String {
+  let mut vars = Vec::new();
+  match value {
+    Value::Object(_) | Value::Array(_) => flatten("", value, &mut vars),
+    other => vars.push(("value".to_string(), scalar(other))),
+  }
+
+  vars
+    .iter()
+    .map(|(key, value)| format!("{}={}", env_name(key), shell_quote(value)))
+    .collect::<Vec<String>>()
+    .join("\n")
+}
+
+fn env_name(key: &str) -> String {
+  let name: String = key
+    .chars()
+    .map(|c| {
+      if c.is_ascii_alphanumeric() {
+        c.to_ascii_uppercase()
+      } else {
+        '_'
+      }
+    })
+    .collect();
+  format!("KEY_{}", name)
+}
+
+fn shell_quote(value: &str) -> String {
+  format!("'{}'", value.replace('\'', "'\\''"))
+}
@@ -0,0 +1,324 @@
+use std::ops::Range;
+
+use anyhow::{anyhow, Result};
+
+use crate::{Database, KeyReference, ReferenceTarget, REFERENCE_SCHEME};
+
+/// A reference found in a template.
+#[derive(Debug, PartialEq)]
+pub struct TemplateReference {
+  pub reference: KeyReference,
+  /// Line the reference starts on, from 1
+  pub line: usize,
+  /// Bytes of the template the reference takes up
+  span: Range<usize>,
+}
+
+/// Finds all references in a template. Two forms are supported:
+/// - `{{ key "Group/Entry" "Field" }}`, where the field defaults to Password,
+/// - `key://Group/Entry/Field` and the other forms of [KeyReference], which end
+///   at whitespace or a quote.
+///
+/// Other `{{ ... }}` blocks, like Helm or Go templates, are left alone.
+pub fn template_references(template: &str) -> Result<Vec<TemplateReference>> {
+  let mut references = Vec::new();
+  let mut rest = 0;
+
+  while rest < template.len() {
+    let next_block = template[rest..].find("{{").map(|i| rest + i);
+    let next_uri = template[rest..].find(REFERENCE_SCHEME).map(|i| rest + i);
+
+    let reference = match (next_block, next_uri) {
+      (None, None) => break,
+      (None, Some(uri)) => parse_uri(template, uri)?,
+      (Some(block), Some(uri)) if uri < block => parse_uri(template, uri)?,
+      (Some(block), _) => match parse_block(template, block)? {
+        Some(reference) => reference,
+        None => {
+          rest = block + 2;
+          continue;
+        }
+      },
+    };
+    rest = reference.span.end;
+    references.push(reference);
+  }
+
+  Ok(references)
+}
+
+fn line_of(template: &str, offset: usize) -> usize {
+  template[..offset].matches('\n').count() + 1
+}
+
+/// Parses `{{ key "entry" "field" }}` at `start`. Blocks that don't call `key`
+/// are not ours and give `None`.
+fn parse_block(template: &str, start: usize) -> Result<Option<TemplateReference>> {
+  let line = line_of(template, start);
+  let inner = template[start + 2..].trim_start();
+  let inner = match inner.strip_prefix("key") {
+    Some(inner) if inner.starts_with(char::is_whitespace) => inner,
+    _ => return Ok(None),
+  };
+
+  let mut args = Vec::new();
+  let mut chars = inner.char_indices();
+  let end = loop {
+    match chars.next() {
+      Some((_, c)) if c.is_whitespace() => continue,
+      Some((i, '}')) if inner[i..].starts_with("}}") => break i + 2,
+      Some((_, '"')) => {
+        let mut arg = String::new();
+        loop {
+          match chars.next() {
+            Some((_, '"')) => break,
+            Some((_, '\\')) => match chars.next() {
+              Some((_, c)) => arg.push(c),
+              None => return Err(anyhow!("line {}: Unterminated string", line)),
+            },
+            Some((_, c)) => arg.push(c),
+            None => return Err(anyhow!("line {}: Unterminated string", line)),
+          }
+        }
+        args.push(arg);
+      }
+      _ => {
+        return Err(anyhow!(
+          "line {}: Invalid reference, use {{{{ key \"Group/Entry\" \"Field\" }}}}",
+          line
+        ))
+      }
+    }
+  };
+
+  let reference = match args.as_slice() {
+    [entry] => KeyReference {
+      entry: entry.clone(),
+      target: ReferenceTarget::Field("Password".to_string()),
+    },
+    [entry, field] => KeyReference {
+      entry: entry.clone(),
+      target: ReferenceTarget::Field(field.clone()),
+    },
+    _ => {
+      return Err(anyhow!(
+        "line {}: key takes an entry and an optional field, got {} arguments",
+        line,
+        args.len()
+      ))
+    }
+  };
+
+  let offset = template.len() - inner.len();
+  Ok(Some(TemplateReference {
+    reference,
+    line,
+    span: start..offset + end,
+  }))
+}
+
+/// Parses a `key://` reference at `start`.
+fn parse_uri(template: &str, start: usize) -> Result<TemplateReference> {
+  let line = line_of(template, start);
+  let end = template[start..]
+    .find(|c: char| c.is_whitespace() || "\"'`<>".contains(c))
+    .map_or(template.len(), |i| start + i);
+
+  let reference = KeyReference::parse(&template[start..end])
+    .map_err(|err| anyhow!("line {}: {}", line, err))?;
+  Ok(TemplateReference {
+    reference,
+    line,
+    span: start..end,
+  })
+}
+
+/// Checks that every reference in the template resolves. Returns the number of
+/// references, or an error listing all that don't resolve.
+pub fn check_template(db: &Database, template: &str) -> Result<usize> {
+  let references = template_references(template)?;
+  resolve_references(db, &references)?;
+  Ok(references.len())
+}
+
+/// Replaces every reference in the template with its value. Fails if any
+/// reference does not resolve.
+pub fn render_template(db: &Database, template: &str) -> Result<String> {
+  let references = template_references(template)?;
+  let values = resolve_references(db, &references)?;
+
+  let mut rendered = String::with_capacity(template.len());
+  let mut rest = 0;
+  for (reference, value) in references.iter().zip(values) {
+    rendered.push_str(&template[rest..reference.span.start]);
+    rendered.push_str(&value);
+    rest = reference.span.end;
+  }
+  rendered.push_str(&template[rest..]);
+  Ok(rendered)
+}
+
+fn resolve_references(
+  db: &Database,
+  references: &[TemplateReference],
+) -> Result<Vec<String>> {
+  let mut values = Vec::new();
+  let mut errors = Vec::new();
+  for reference in references {
+    match reference.reference.resolve(db) {
+      Ok(value) => values.push(value),
+      Err(err) => errors.push(format!(
+        "  line {}: {} ({})",
+        reference.line, err, reference.reference
+      )),
+    }
+  }
+
+  match errors.is_empty() {
+    true => Ok(values),
+    false => Err(anyhow!(
+      "{} of {} references could not be resolved:\n{}",
+      errors.len(),
+      references.len(),
+      errors.join("\n")
+    )),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use keepass::{
+    config::DatabaseConfig,
+    db::{Entry, Group, Value},
+  };
+
+  use super::*;
+
+  fn entry(title: &str, password: &str) -> Entry {
+    let mut entry = Entry::new();
+    entry
+      .fields
+      .insert("Title".to_string(), Value::Unprotected(title.to_string()));
+    entry.fields.insert(
+      "UserName".to_string(),
+      Value::Unprotected("tim".to_string()),
+    );
+    entry
+      .fields
+      .insert("Password".to_string(), Value::protected(password));
+    entry
+  }
+
+  fn database() -> Database {
+    let mut db = Database::new(DatabaseConfig::default());
+    db.root.entries.push(entry("github", "gh-secret"));
+    let mut prod = Group::new("Prod");
+    prod.entries.push(entry("My DB", "db-secret"));
+    db.root.groups.push(prod);
+    db
+  }
+
+  fn references(template: &str) -> Vec<KeyReference> {
+    template_references(template)
+      .unwrap()
+      .into_iter()
+      .map(|reference| reference.reference)
+      .collect()
+  }
+
+  fn field(entry: &str, field: &str) -> KeyReference {
+    KeyReference {
+      entry: entry.to_string(),
+      target: ReferenceTarget::Field(field.to_string()),
+    }
+  }
+
+  #[test]
+  fn block_field_defaults_to_password() {
+    assert_eq!(
+      references(r#"a {{ key "Prod/My DB" }} b {{key "github" "UserName"}}"#),
+      vec![field("Prod/My DB", "Password"), field("github", "UserName")]
+    );
+  }
+
+  #[test]
+  fn block_arguments_take_escapes() {
+    assert_eq!(
+      references(r#"{{ key "say \"hi\"" "back\\slash" }}"#),
+      vec![field(r#"say "hi""#, r"back\slash")]
+    );
+  }
+
+  #[test]
+  fn other_blocks_are_left_alone() {
+    assert!(references("{{ .Values.name }} {{ keys }} {{- if x }}").is_empty());
+  }
+
+  #[test]
+  fn invalid_blocks_fail_with_their_line() {
+    let err = template_references("\n{{ key \"github }}").unwrap_err();
+    assert_eq!(err.to_string(), "line 2: Unterminated string");
+    assert!(template_references("{{ key github }}").is_err());
+    assert!(template_references(r#"{{ key "a" "b" "c" }}"#).is_err());
+  }
+
+  #[test]
+  fn uri_ends_at_whitespace_and_quotes() {
+    let template =
+      "url=key://github/UserName\n\"key://Prod/My%20DB/Password\" <key://github?otp>";
+    let found = template_references(template).unwrap();
+    assert_eq!(
+      found
+        .iter()
+        .map(|r| r.reference.clone())
+        .collect::<Vec<_>>(),
+      vec![
+        field("github", "UserName"),
+        field("Prod/My DB", "Password"),
+        KeyReference {
+          entry: "github".to_string(),
+          target: ReferenceTarget::Otp,
+        },
+      ]
+    );
+    assert_eq!(
+      found.iter().map(|r| r.line).collect::<Vec<_>>(),
+      vec![1, 2, 2]
+    );
+  }
+
+  #[test]
+  fn uri_takes_uuid_prefix() {
+    let uuid = "0e6d3a6c-1d4b-4d4c-9a3e-6f2b7c1a9f00";
+    assert_eq!(
+      references(&format!("key://uuid:{}/Password", uuid)),
+      vec![field(&format!("uuid:{}", uuid), "Password")]
+    );
+  }
+
+  #[test]
+  fn render_replaces_references() {
+    let db = database();
+    let uuid = db.root.entry_by_name("github").map(|e| e.uuid);
+    let template = format!(
+      "user={{{{ key \"github\" \"UserName\" }}}} db='key://Prod/My%20DB/Password' \
+       gh=key://uuid:{}/Password {{{{ .Other }}}}",
+      uuid.unwrap()
+    );
+    assert_eq!(
+      render_template(&db, &template).unwrap(),
+      "user=tim db='db-secret' gh=gh-secret {{ .Other }}"
+    );
+  }
+
+  #[test]
+  fn unresolved_references_are_all_listed() {
+    let db = database();
+    let template = "key://missing/Password\n{{ key \"github\" \"Nope\" }}";
+    let err = check_template(&db, template).unwrap_err().to_string();
+    assert!(err.starts_with("2 of 2 references could not be resolved"));
+    assert!(err.contains("line 1: Entry not found"));
+    assert!(err.contains("line 2: Field \"Nope\" not found"));
+    assert_eq!(check_template(&db, "key://github/UserName").unwrap(), 1);
+  }
+}
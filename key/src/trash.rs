@@ -0,0 +1,285 @@
+use anyhow::{anyhow, Result};
+use chrono::NaiveDateTime;
+use keepass::db::{CustomDataItem, CustomDataValue, Times};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use uuid::Uuid;
+
+use crate::{
+  contains_node, count_entries, find_entry, find_group, find_group_mut, get_group,
+  group_path, take_node, Database, Group, Node, UUID_PREFIX,
+};
+use crate::{group::add_node, key::ambiguous_name};
+
+const RECYCLE_BIN_NAME: &str = "Recycle Bin";
+/// Icon KeePass and KeePassXC use for the recycle bin group
+const RECYCLE_BIN_ICON: usize = 43;
+/// Custom data key holding the uuid of the group a node was deleted from
+const PREVIOUS_PARENT_KEY: &str = "key.PreviousParentGroup";
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct KeyTrashItem {
+  pub uuid: String,
+  pub title: String,
+  /// Whether this is a deleted group rather than an entry
+  pub group: bool,
+  /// Group path the item was deleted from, if it still exists
+  pub previous_parent: Option<String>,
+  pub deleted: Option<NaiveDateTime>,
+}
+
+/// Deletes an entry the way KeePass does: it is moved into the recycle bin,
+/// which gets created on first use. Entries that already are in the recycle
+/// bin, databases with a disabled recycle bin and `permanent` deletes remove the
+/// entry for good and record it in the deleted objects, so merges and syncs
+/// don't bring it back.
+pub fn delete_entry(db: &mut Database, name: &str, permanent: bool) -> Result<()> {
+  let uuid = find_entry(db, name)?.uuid;
+  delete_node(db, &uuid, permanent);
+  Ok(())
+}
+
+/// Deletes a group like [delete_entry] does with entries. Groups that are not
+/// empty are only deleted with `recursive`.
+pub fn delete_group(
+  db: &mut Database,
+  path: &str,
+  recursive: bool,
+  permanent: bool,
+) -> Result<()> {
+  let group = get_group(db, path)?;
+  let uuid = group.uuid;
+  if uuid == db.root.uuid {
+    return Err(anyhow!("Can not delete the root group"));
+  }
+  if recycle_bin(db)
+    .is_some_and(|bin| bin.uuid == uuid || contains_node(group, &bin.uuid))
+  {
+    return Err(anyhow!("Use `key trash empty` to empty the recycle bin"));
+  }
+  if !recursive && (!group.entries.is_empty() || !group.groups.is_empty()) {
+    return Err(anyhow!(
+      "Group \"{}\" is not empty, use --recursive to delete it with everything in it",
+      path
+    ));
+  }
+
+  delete_node(db, &uuid, permanent);
+  Ok(())
+}
+
+fn delete_node(db: &mut Database, uuid: &Uuid, permanent: bool) {
+  let in_recycle_bin = recycle_bin(db).is_some_and(|bin| contains_node(bin, uuid));
+  let (mut node, parent) = take_node(&mut db.root, uuid).expect("Node should exist");
+
+  if permanent || in_recycle_bin || db.meta.recyclebin_enabled == Some(false) {
+    record_deletion(db, &node);
+    return;
+  }
+
+  let (custom_data, times) = match &mut node {
+    Node::Entry(e) => (&mut e.custom_data, &mut e.times),
+    Node::Group(g) => (&mut g.custom_data, &mut g.times),
+  };
+  custom_data.insert(
+    PREVIOUS_PARENT_KEY.to_string(),
+    CustomDataItem {
+      value: Some(CustomDataValue::String(parent.to_string())),
+      last_modification_time: Some(Times::now()),
+    },
+  );
+  times.location_changed = Some(Times::now());
+  add_node(recycle_bin_mut(db), node);
+}
+
+/// The recycle bin group of the database, if it has one.
+pub fn recycle_bin(db: &Database) -> Option<&Group> {
+  let uuid = db.meta.recyclebin_uuid?;
+  find_group(&db.root, &uuid)
+}
+
+pub fn list_trash(db: &Database) -> Vec<KeyTrashItem> {
+  let bin = match recycle_bin(db) {
+    Some(bin) => bin,
+    None => return Vec::new(),
+  };
+
+  let item = |uuid: Uuid, title: &str, group, custom_data, times: &Times| KeyTrashItem {
+    uuid: uuid.to_string(),
+    title: title.to_string(),
+    group,
+    previous_parent: previous_parent(custom_data)
+      .and_then(|uuid| group_path(&db.root, &uuid, "")),
+    deleted: times.location_changed,
+  };
+
+  let entries = bin.entries.iter().map(|e| {
+    let title = e.get_title().unwrap_or_default();
+    item(e.uuid, title, false, &e.custom_data, &e.times)
+  });
+  let groups = bin
+    .groups
+    .iter()
+    .map(|g| item(g.uuid, &g.name, true, &g.custom_data, &g.times));
+  entries.chain(groups).collect()
+}
+
+/// Moves an entry or group out of the recycle bin back into the group it was
+/// deleted from. Items whose group is gone end up in the root group. `name` is
+/// the title in the recycle bin or "uuid:<uuid>", a title several items share
+/// fails with a list of their uuids.
+pub fn restore_trash_entry(db: &mut Database, name: &str) -> Result<()> {
+  let bin = recycle_bin(db);
+  let uuid = match name.strip_prefix(UUID_PREFIX) {
+    Some(uuid) => Uuid::parse_str(uuid).ok().filter(|uuid| {
+      bin.is_some_and(|bin| {
+        bin.groups.iter().any(|g| g.uuid == *uuid)
+          || bin.entries.iter().any(|e| e.uuid == *uuid)
+      })
+    }),
+    None => {
+      let entries = bin.iter().flat_map(|bin| bin.entries.iter());
+      let groups = bin.iter().flat_map(|bin| bin.groups.iter());
+      let candidates: Vec<(Uuid, String)> = entries
+        .filter(|e| e.get_title() == Some(name))
+        .map(|e| {
+          let username = e.get_username().unwrap_or_default();
+          (e.uuid, format!("{} ({})", name, username))
+        })
+        .chain(
+          groups
+            .filter(|g| g.name == name)
+            .map(|g| (g.uuid, format!("{}/", name))),
+        )
+        .collect();
+      match candidates.as_slice() {
+        [] => None,
+        [(uuid, _)] => Some(*uuid),
+        _ => return Err(ambiguous_name(name, "items", candidates)),
+      }
+    }
+  }
+  .ok_or(anyhow!("\"{}\" not found in the recycle bin", name))?;
+
+  let (mut node, _) = take_node(&mut db.root, &uuid).expect("Node should exist");
+  let (custom_data, times) = match &mut node {
+    Node::Entry(e) => (&mut e.custom_data, &mut e.times),
+    Node::Group(g) => (&mut g.custom_data, &mut g.times),
+  };
+  let parent = previous_parent(custom_data);
+  custom_data.remove(PREVIOUS_PARENT_KEY);
+  times.location_changed = Some(Times::now());
+
+  match parent.and_then(|uuid| find_group_mut(&mut db.root, &uuid)) {
+    Some(group) => add_node(group, node),
+    None => add_node(&mut db.root, node),
+  }
+
+  Ok(())
+}
+
+/// Permanently deletes everything in the recycle bin. Returns the number of
+/// removed entries.
+pub fn empty_trash(db: &mut Database) -> usize {
+  let uuid = match db.meta.recyclebin_uuid {
+    Some(uuid) => uuid,
+    None => return 0,
+  };
+  let (groups, entries) = match find_group_mut(&mut db.root, &uuid) {
+    Some(bin) => (
+      std::mem::take(&mut bin.groups),
+      std::mem::take(&mut bin.entries),
+    ),
+    None => return 0,
+  };
+
+  let count = entries.len() + groups.iter().map(count_entries).sum::<usize>();
+  for group in groups.iter() {
+    record_group_deletion(db, group);
+  }
+  for entry in entries.iter() {
+    db.deleted_objects.insert(entry.uuid, Some(Times::now()));
+  }
+  count
+}
+
+fn recycle_bin_mut(db: &mut Database) -> &mut Group {
+  let existing = db
+    .meta
+    .recyclebin_uuid
+    .filter(|uuid| find_group(&db.root, uuid).is_some());
+
+  let uuid = match existing {
+    Some(uuid) => uuid,
+    None => {
+      let mut bin = Group::new(RECYCLE_BIN_NAME);
+      bin.icon_id = Some(RECYCLE_BIN_ICON);
+      bin.enable_autotype = Some(false);
+      bin.enable_searching = Some(false);
+      let uuid = bin.uuid;
+      db.root.groups.push(bin);
+      db.meta.recyclebin_enabled = Some(true);
+      db.meta.recyclebin_uuid = Some(uuid);
+      db.meta.recyclebin_changed = Some(Times::now());
+      uuid
+    }
+  };
+
+  find_group_mut(&mut db.root, &uuid).expect("Recycle bin should exist")
+}
+
+/// Records a node and everything below it as deleted.
+fn record_deletion(db: &mut Database, node: &Node) {
+  match node {
+    Node::Entry(e) => {
+      db.deleted_objects.insert(e.uuid, Some(Times::now()));
+    }
+    Node::Group(g) => record_group_deletion(db, g),
+  }
+}
+
+fn record_group_deletion(db: &mut Database, group: &Group) {
+  for e in group.entries.iter() {
+    db.deleted_objects.insert(e.uuid, Some(Times::now()));
+  }
+  for g in group.groups.iter() {
+    record_group_deletion(db, g);
+  }
+  db.deleted_objects.insert(group.uuid, Some(Times::now()));
+}
+
+fn previous_parent(custom_data: &HashMap<String, CustomDataItem>) -> Option<Uuid> {
+  match custom_data.get(PREVIOUS_PARENT_KEY)?.value.as_ref()? {
+    CustomDataValue::String(uuid) => Uuid::parse_str(uuid).ok(),
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use keepass::config::DatabaseConfig;
+
+  use super::*;
+  use crate::{list_entries, new_entry};
+
+  #[test]
+  fn restore_refuses_ambiguous_titles() {
+    let mut db = Database::new(DatabaseConfig::default());
+    for user in ["tim", "tom"] {
+      new_entry(&mut db, "mail", Some(user), None).unwrap();
+      delete_entry(&mut db, "mail", false).unwrap();
+    }
+
+    let err = restore_trash_entry(&mut db, "mail")
+      .unwrap_err()
+      .to_string();
+    assert!(err.starts_with("\"mail\" matches 2 items, use one of:\n"));
+    assert!(err.contains("mail (tim)") && err.contains("mail (tom)"));
+
+    let uuid = list_trash(&db)[1].uuid.clone();
+    restore_trash_entry(&mut db, &format!("{}{}", UUID_PREFIX, uuid)).unwrap();
+    let restored = find_entry(&db, "mail").unwrap();
+    assert_eq!(restored.uuid.to_string(), uuid);
+    assert_eq!(list_entries(&db).len(), 1);
+  }
+}
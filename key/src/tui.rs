@@ -0,0 +1,1216 @@
+use std::{
+  collections::HashSet,
+  time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, Result};
+use ratatui::{
+  crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers},
+  layout::{Constraint, Layout, Position, Rect},
+  style::{Color, Modifier, Style, Stylize},
+  text::{Line, Span},
+  widgets::{
+    Block, Clear, Gauge, List, ListItem, ListState, Paragraph, Row, Table, TableState,
+  },
+  DefaultTerminal, Frame,
+};
+use uuid::Uuid;
+
+use crate::{
+  backup_entry,
+  db::{get_database, get_database_key, write_database, KeeOptions},
+  delete_entry, entry_expiry, find_entry, find_entry_mut, find_group, group_path,
+  is_expired, new_entry, otp_details, search_entries, walk_entries, Database,
+  DatabaseKey, Entry, Group, PasswordPolicy, Value, UUID_PREFIX,
+};
+
+/// Seconds without input before the vault locks, unless the profile sets it
+pub const DEFAULT_LOCK_TIMEOUT: u64 = 300;
+
+/// Fields every entry shows, in this order, even when they are empty
+const STANDARD_FIELDS: [&str; 5] = ["Title", "UserName", "Password", "URL", "Notes"];
+const OTP_FIELD: &str = "otp";
+const HIDDEN: &str = "••••••••";
+
+#[derive(Clone, Copy, PartialEq)]
+enum Pane {
+  Groups,
+  Entries,
+  Fields,
+}
+
+enum Mode {
+  Normal,
+  Search,
+  /// Editing the value of a field
+  Edit {
+    field: String,
+    input: Input,
+  },
+  /// Asking for the name of a new field
+  AddField {
+    input: Input,
+  },
+  /// Asking for the title of a new entry
+  NewEntry {
+    input: Input,
+  },
+  ConfirmDelete,
+  Locked {
+    input: Input,
+  },
+}
+
+/// A text input with a cursor, which may span several lines.
+#[derive(Default)]
+struct Input {
+  value: String,
+  /// Byte offset into `value`
+  cursor: usize,
+}
+
+impl Input {
+  fn new(value: &str) -> Self {
+    Input {
+      value: value.to_string(),
+      cursor: value.len(),
+    }
+  }
+
+  fn insert(&mut self, c: char) {
+    self.value.insert(self.cursor, c);
+    self.cursor += c.len_utf8();
+  }
+
+  fn prev_len(&self) -> usize {
+    self.value[..self.cursor]
+      .chars()
+      .next_back()
+      .map_or(0, char::len_utf8)
+  }
+
+  fn next_len(&self) -> usize {
+    self.value[self.cursor..]
+      .chars()
+      .next()
+      .map_or(0, char::len_utf8)
+  }
+
+  /// Applies an editing key, others are ignored.
+  fn handle(&mut self, key: KeyEvent) {
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    match key.code {
+      KeyCode::Char('u') if ctrl => {
+        self.value.drain(..self.cursor);
+        self.cursor = 0;
+      }
+      KeyCode::Char(c) if !ctrl => self.insert(c),
+      KeyCode::Backspace => {
+        let len = self.prev_len();
+        self.cursor -= len;
+        self.value.drain(self.cursor..self.cursor + len);
+      }
+      KeyCode::Delete => {
+        let len = self.next_len();
+        self.value.drain(self.cursor..self.cursor + len);
+      }
+      KeyCode::Left => self.cursor -= self.prev_len(),
+      KeyCode::Right => self.cursor += self.next_len(),
+      KeyCode::Home => self.cursor = 0,
+      KeyCode::End => self.cursor = self.value.len(),
+      _ => {}
+    }
+  }
+
+  /// Line and column of the cursor.
+  fn position(&self) -> (u16, u16) {
+    let before = &self.value[..self.cursor];
+    let line = before.matches('\n').count();
+    let column = before
+      .rsplit('\n')
+      .next()
+      .unwrap_or_default()
+      .chars()
+      .count();
+    (line as u16, column as u16)
+  }
+}
+
+struct Status {
+  message: String,
+  error: bool,
+}
+
+struct GroupRow {
+  uuid: Uuid,
+  depth: usize,
+  name: String,
+  entries: usize,
+  has_groups: bool,
+}
+
+struct App<'a> {
+  options: &'a KeeOptions,
+  copy: &'a dyn Fn(String) -> Result<()>,
+  key: Option<DatabaseKey>,
+  db: Option<Database>,
+  pane: Pane,
+  mode: Mode,
+  /// Input that was in progress when the vault locked, resumed on unlock
+  suspended: Option<Mode>,
+  /// Groups whose subgroups are hidden in the tree
+  collapsed: HashSet<Uuid>,
+  group: Uuid,
+  entry: Option<Uuid>,
+  field: usize,
+  query: String,
+  reveal: bool,
+  /// Whether the last change could not be written
+  dirty: bool,
+  /// Whether the last key asked to quit despite `dirty`
+  quit_unsaved: bool,
+  quit: bool,
+  status: Option<Status>,
+  lock_after: Option<Duration>,
+  last_input: Instant,
+  group_state: ListState,
+  entry_state: ListState,
+  field_state: TableState,
+}
+
+/// Browses and edits the database in a full screen terminal ui, with a group
+/// tree, the entries of the selected group and the fields of the selected
+/// entry. Every change is written with [write_database] right away. After
+/// `lock_after` seconds without input the database is closed until the
+/// password is entered again, 0 never locks. `copy` puts a value on the
+/// clipboard.
+pub async fn run_tui(
+  options: &KeeOptions,
+  db: Database,
+  key: DatabaseKey,
+  lock_after: u64,
+  copy: &dyn Fn(String) -> Result<()>,
+) -> Result<()> {
+  let mut app = App::new(options, db, key, lock_after, copy);
+  let mut terminal = ratatui::init();
+  let result = app.run(&mut terminal).await;
+  ratatui::restore();
+  result
+}
+
+impl<'a> App<'a> {
+  fn new(
+    options: &'a KeeOptions,
+    db: Database,
+    key: DatabaseKey,
+    lock_after: u64,
+    copy: &'a dyn Fn(String) -> Result<()>,
+  ) -> Self {
+    App {
+      options,
+      copy,
+      group: db.root.uuid,
+      key: Some(key),
+      db: Some(db),
+      pane: Pane::Entries,
+      mode: Mode::Normal,
+      suspended: None,
+      collapsed: HashSet::new(),
+      entry: None,
+      field: 0,
+      query: String::new(),
+      reveal: false,
+      dirty: false,
+      quit_unsaved: false,
+      quit: false,
+      status: None,
+      lock_after: (lock_after > 0).then_some(Duration::from_secs(lock_after)),
+      last_input: Instant::now(),
+      group_state: ListState::default(),
+      entry_state: ListState::default(),
+      field_state: TableState::default(),
+    }
+  }
+
+  async fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
+    while !self.quit {
+      self.sync();
+      terminal.draw(|frame| self.draw(frame))?;
+
+      // redraw at least every 250ms for the otp countdown and the lock timeout
+      if event::poll(Duration::from_millis(250))? {
+        if let Event::Key(key) = event::read()? {
+          if key.kind == KeyEventKind::Press {
+            self.last_input = Instant::now();
+            self.status = None;
+            if let Err(err) = self.on_key(key).await {
+              self.error(err.to_string());
+            }
+          }
+        }
+      }
+
+      let idle = self
+        .lock_after
+        .is_some_and(|after| self.last_input.elapsed() >= after);
+      if idle && self.db.is_some() {
+        if let Err(err) = self.lock().await {
+          self.error(err.to_string());
+        }
+      }
+    }
+    Ok(())
+  }
+
+  fn info(&mut self, message: String) {
+    self.status = Some(Status {
+      message,
+      error: false,
+    });
+  }
+
+  fn error(&mut self, message: String) {
+    self.status = Some(Status {
+      message,
+      error: true,
+    });
+  }
+
+  /// Keeps the selection on existing groups, entries and fields after changes.
+  fn sync(&mut self) {
+    let Some(db) = &self.db else { return };
+    if find_group(&db.root, &self.group).is_none() {
+      self.group = db.root.uuid;
+    }
+    let entries = entry_list(db, &self.group, &self.query);
+    if !entries.iter().any(|(_, e)| Some(e.uuid) == self.entry) {
+      self.entry = entries.first().map(|(_, e)| e.uuid);
+    }
+    let fields = self.selected_entry().map_or(0, |e| field_names(e).len());
+    self.field = self.field.min(fields.saturating_sub(1));
+  }
+
+  fn selected_entry(&self) -> Option<&Entry> {
+    let db = self.db.as_ref()?;
+    let uuid = self.entry?;
+    find_entry(db, &entry_name(&uuid)).ok()
+  }
+
+  fn selected_field(&self) -> Option<String> {
+    self
+      .selected_entry()
+      .and_then(|e| field_names(e).into_iter().nth(self.field))
+  }
+
+  fn db_mut(&mut self) -> Result<&mut Database> {
+    self.db.as_mut().ok_or(anyhow!("The vault is locked"))
+  }
+
+  async fn on_key(&mut self, key: KeyEvent) -> Result<()> {
+    let confirmed = std::mem::take(&mut self.quit_unsaved);
+    if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+      return self.request_quit(confirmed);
+    }
+
+    match std::mem::replace(&mut self.mode, Mode::Normal) {
+      Mode::Normal => self.on_normal_key(key, confirmed).await,
+      Mode::Search => {
+        match key.code {
+          KeyCode::Esc => self.query.clear(),
+          KeyCode::Enter => self.pane = Pane::Entries,
+          KeyCode::Down => {
+            self.move_selection(Pane::Entries, 1);
+            self.mode = Mode::Search;
+          }
+          KeyCode::Up => {
+            self.move_selection(Pane::Entries, -1);
+            self.mode = Mode::Search;
+          }
+          KeyCode::Backspace => {
+            self.query.pop();
+            self.mode = Mode::Search;
+          }
+          KeyCode::Char(c) => {
+            self.query.push(c);
+            self.mode = Mode::Search;
+          }
+          _ => self.mode = Mode::Search,
+        }
+        Ok(())
+      }
+      Mode::Edit { field, mut input } => {
+        let newline = key.modifiers.contains(KeyModifiers::ALT)
+          || key.modifiers.contains(KeyModifiers::CONTROL);
+        match key.code {
+          KeyCode::Esc => Ok(()),
+          KeyCode::Enter if newline => {
+            input.insert('\n');
+            self.mode = Mode::Edit { field, input };
+            Ok(())
+          }
+          KeyCode::Char('j') if newline => {
+            input.insert('\n');
+            self.mode = Mode::Edit { field, input };
+            Ok(())
+          }
+          KeyCode::Enter => self.set_field(&field, &input.value).await,
+          _ => {
+            input.handle(key);
+            self.mode = Mode::Edit { field, input };
+            Ok(())
+          }
+        }
+      }
+      Mode::AddField { mut input } => match key.code {
+        KeyCode::Esc => Ok(()),
+        KeyCode::Enter => {
+          let field = input.value.trim().to_string();
+          let entry = self.selected_entry().ok_or(anyhow!("No entry selected"))?;
+          if field.is_empty() {
+            return Err(anyhow!("Fields need a name"));
+          }
+          if field_names(entry).contains(&field) {
+            return Err(anyhow!("Field \"{}\" already exists", field));
+          }
+          self.mode = Mode::Edit {
+            field,
+            input: Input::default(),
+          };
+          Ok(())
+        }
+        _ => {
+          input.handle(key);
+          self.mode = Mode::AddField { input };
+          Ok(())
+        }
+      },
+      Mode::NewEntry { mut input } => match key.code {
+        KeyCode::Esc => Ok(()),
+        KeyCode::Enter => self.create_entry(input.value.trim()).await,
+        _ => {
+          input.handle(key);
+          self.mode = Mode::NewEntry { input };
+          Ok(())
+        }
+      },
+      Mode::ConfirmDelete => match key.code {
+        KeyCode::Char('y') => self.delete_entry().await,
+        _ => Ok(()),
+      },
+      Mode::Locked { mut input } => {
+        match key.code {
+          KeyCode::Esc => self.quit = true,
+          KeyCode::Enter => {
+            if let Err(err) = self.unlock(&input.value).await {
+              self.mode = Mode::Locked {
+                input: Input::default(),
+              };
+              return Err(err);
+            }
+          }
+          _ => {
+            input.handle(key);
+            self.mode = Mode::Locked { input };
+          }
+        }
+        Ok(())
+      }
+    }
+  }
+
+  /// Quits, unless the last change could not be saved and quitting was not
+  /// asked for twice in a row.
+  fn request_quit(&mut self, confirmed: bool) -> Result<()> {
+    if self.dirty && !confirmed {
+      self.quit_unsaved = true;
+      return Err(anyhow!(
+        "The last change could not be saved, quit again to discard it"
+      ));
+    }
+    self.quit = true;
+    Ok(())
+  }
+
+  async fn on_normal_key(&mut self, key: KeyEvent, confirmed: bool) -> Result<()> {
+    match key.code {
+      KeyCode::Char('q') => self.request_quit(confirmed)?,
+      KeyCode::Esc if !self.query.is_empty() => self.query.clear(),
+      KeyCode::Tab | KeyCode::Right | KeyCode::Char('l') => {
+        self.pane = match self.pane {
+          Pane::Groups => Pane::Entries,
+          _ => Pane::Fields,
+        }
+      }
+      KeyCode::BackTab | KeyCode::Left | KeyCode::Char('h') => {
+        self.pane = match self.pane {
+          Pane::Fields => Pane::Entries,
+          _ => Pane::Groups,
+        }
+      }
+      KeyCode::Down | KeyCode::Char('j') => self.move_selection(self.pane, 1),
+      KeyCode::Up | KeyCode::Char('k') => self.move_selection(self.pane, -1),
+      KeyCode::Char(' ') if self.pane == Pane::Groups => self.toggle_group(),
+      KeyCode::Enter if self.pane == Pane::Groups => self.pane = Pane::Entries,
+      KeyCode::Enter if self.pane == Pane::Entries => self.pane = Pane::Fields,
+      KeyCode::Enter | KeyCode::Char('e') => self.start_edit()?,
+      KeyCode::Char('/') => {
+        self.mode = Mode::Search;
+        self.pane = Pane::Entries;
+      }
+      KeyCode::Char('r') => self.reveal = !self.reveal,
+      KeyCode::Char('c') => {
+        let options = self.options;
+        self.copy_field(&options.default_field)?
+      }
+      KeyCode::Char('u') => self.copy_field("UserName")?,
+      KeyCode::Char('o') => self.copy_field(OTP_FIELD)?,
+      KeyCode::Char('y') => {
+        let field = self.selected_field().ok_or(anyhow!("No field selected"))?;
+        self.copy_field(&field)?
+      }
+      KeyCode::Char('a') => {
+        self.selected_entry().ok_or(anyhow!("No entry selected"))?;
+        self.mode = Mode::AddField {
+          input: Input::default(),
+        };
+      }
+      KeyCode::Char('x') => self.remove_field().await?,
+      KeyCode::Char('g') => self.generate_password().await?,
+      KeyCode::Char('n') => {
+        self.mode = Mode::NewEntry {
+          input: Input::default(),
+        }
+      }
+      KeyCode::Char('d') | KeyCode::Delete => {
+        self.selected_entry().ok_or(anyhow!("No entry selected"))?;
+        self.mode = Mode::ConfirmDelete;
+      }
+      KeyCode::Char('L') => self.lock().await?,
+      _ => {}
+    }
+    Ok(())
+  }
+
+  fn move_selection(&mut self, pane: Pane, delta: isize) {
+    let Some(db) = &self.db else { return };
+    let step = |index: Option<usize>, len: usize| {
+      let index = index.map_or(0, |i| i as isize + delta);
+      index.clamp(0, len.saturating_sub(1) as isize) as usize
+    };
+    match pane {
+      Pane::Groups => {
+        let rows = group_rows(db, &self.collapsed);
+        let index = rows.iter().position(|r| r.uuid == self.group);
+        self.group = rows[step(index, rows.len())].uuid;
+        self.entry = None;
+        self.field = 0;
+      }
+      Pane::Entries => {
+        let entries = entry_list(db, &self.group, &self.query);
+        let index = entries.iter().position(|(_, e)| Some(e.uuid) == self.entry);
+        self.entry = entries.get(step(index, entries.len())).map(|(_, e)| e.uuid);
+        self.field = 0;
+      }
+      Pane::Fields => {
+        let len = self.selected_entry().map_or(0, |e| field_names(e).len());
+        self.field = step(Some(self.field), len);
+      }
+    }
+  }
+
+  /// Shows or hides the subgroups of the selected group.
+  fn toggle_group(&mut self) {
+    if !self.collapsed.remove(&self.group) {
+      self.collapsed.insert(self.group);
+    }
+  }
+
+  fn start_edit(&mut self) -> Result<()> {
+    let entry = self.selected_entry().ok_or(anyhow!("No entry selected"))?;
+    let field = field_names(entry)
+      .into_iter()
+      .nth(self.field)
+      .ok_or(anyhow!("No field selected"))?;
+    let input = Input::new(entry.get(&field).unwrap_or_default());
+    self.pane = Pane::Fields;
+    self.mode = Mode::Edit { field, input };
+    Ok(())
+  }
+
+  fn copy_field(&mut self, field: &str) -> Result<()> {
+    let entry = self.selected_entry().ok_or(anyhow!("No entry selected"))?;
+    let title = entry.get_title().unwrap_or_default().to_string();
+    let value = entry
+      .get(field)
+      .ok_or(anyhow!("Field \"{}\" not found", field))?
+      .to_string();
+    let value = match field {
+      OTP_FIELD => otp_details(value, None, None)?.code,
+      _ => value,
+    };
+    (self.copy)(value)?;
+    self.info(format!("Copied {} of {} to clipboard", field, title));
+    Ok(())
+  }
+
+  /// Writes the database after a change and reports how it went.
+  async fn save(&mut self, message: String) {
+    let (Some(db), Some(key)) = (&mut self.db, &self.key) else {
+      return;
+    };
+    match write_database(self.options, db, key).await {
+      Ok(()) => {
+        self.dirty = false;
+        self.info(message);
+      }
+      Err(err) => {
+        self.dirty = true;
+        self.error(format!("Could not save: {}", err));
+      }
+    }
+  }
+
+  async fn set_field(&mut self, field: &str, value: &str) -> Result<()> {
+    let uuid = self.entry.ok_or(anyhow!("No entry selected"))?;
+    if set_entry_field(self.db_mut()?, &uuid, field, value)? {
+      self.save(format!("Saved {}", field)).await;
+    }
+    Ok(())
+  }
+
+  async fn remove_field(&mut self) -> Result<()> {
+    let uuid = self.entry.ok_or(anyhow!("No entry selected"))?;
+    let field = self.selected_field().ok_or(anyhow!("No field selected"))?;
+    if STANDARD_FIELDS.contains(&field.as_str()) {
+      return Err(anyhow!("{} can not be removed, edit it instead", field));
+    }
+    let db = self.db_mut()?;
+    let entry = find_entry_mut(&mut db.root, &entry_name(&uuid))?;
+    backup_entry(entry, &db.meta);
+    entry.fields.remove(&field);
+    self.save(format!("Removed {}", field)).await;
+    Ok(())
+  }
+
+  async fn generate_password(&mut self) -> Result<()> {
+    let policy = match &self.options.generator {
+      Some(policy) => policy.parse::<PasswordPolicy>()?,
+      None => PasswordPolicy::default(),
+    };
+    self.set_field("Password", &policy.generate()?).await
+  }
+
+  async fn create_entry(&mut self, title: &str) -> Result<()> {
+    if title.is_empty() || title.contains('/') {
+      return Err(anyhow!("Titles can not be empty or contain \"/\""));
+    }
+    let group = self.group;
+    let db = self.db_mut()?;
+    let name = match group_path(&db.root, &group, "").unwrap_or_default() {
+      path if path.is_empty() => title.to_string(),
+      path => format!("{}/{}", path, title),
+    };
+    new_entry(db, &name, None, None)?;
+    self.entry = walk_entries(&db.root)
+      .into_iter()
+      .find(|(path, _)| *path == name)
+      .map(|(_, e)| e.uuid);
+    self.query.clear();
+    self.pane = Pane::Fields;
+    self.field = 0;
+    self.save(format!("Created {}", name)).await;
+    Ok(())
+  }
+
+  async fn delete_entry(&mut self) -> Result<()> {
+    let uuid = self.entry.ok_or(anyhow!("No entry selected"))?;
+    let title = self
+      .selected_entry()
+      .and_then(|e| e.get_title())
+      .unwrap_or_default()
+      .to_string();
+    delete_entry(self.db_mut()?, &entry_name(&uuid), false)?;
+    self.save(format!("Deleted {}", title)).await;
+    Ok(())
+  }
+
+  /// Closes the database, it is read again once the password is entered. While
+  /// the last change can not be saved, the database stays open. An edit in
+  /// progress continues after unlocking.
+  async fn lock(&mut self) -> Result<()> {
+    if self.dirty {
+      self.save("Saved".to_string()).await;
+    }
+    if self.dirty {
+      // try again after another lock period rather than on every redraw
+      self.last_input = Instant::now();
+      let reason = self.status.take().map(|s| s.message).unwrap_or_default();
+      return Err(anyhow!("{}, the vault stays unlocked", reason));
+    }
+    self.status = None;
+    self.db = None;
+    self.key = None;
+    self.reveal = false;
+    let mode = std::mem::replace(
+      &mut self.mode,
+      Mode::Locked {
+        input: Input::default(),
+      },
+    );
+    if let Mode::Edit { .. } | Mode::AddField { .. } | Mode::NewEntry { .. } = mode {
+      self.suspended = Some(mode);
+      self.info("Unlock to continue the unsaved edit".to_string());
+    }
+    Ok(())
+  }
+
+  async fn unlock(&mut self, password: &str) -> Result<()> {
+    let options = KeeOptions {
+      keepassdb_password: Some(password.to_string()),
+      ..self.options.clone()
+    };
+    let key = get_database_key(&options)?;
+    let db = get_database(self.options, &key)
+      .await
+      .map_err(|err| anyhow!("Could not unlock: {}", err))?;
+    self.db = Some(db);
+    self.key = Some(key);
+    self.dirty = false;
+    if let Some(mode) = self.suspended.take() {
+      self.mode = mode;
+    }
+    Ok(())
+  }
+
+  fn draw(&mut self, frame: &mut Frame) {
+    let [main, status] =
+      Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+    self.draw_status(frame, status);
+
+    if let Mode::Locked { input } = &self.mode {
+      draw_locked(frame, main, &self.options.keepassdb, input);
+      return;
+    }
+    let Some(db) = &self.db else { return };
+
+    let [groups, entries, fields] = Layout::horizontal([
+      Constraint::Percentage(25),
+      Constraint::Percentage(30),
+      Constraint::Percentage(45),
+    ])
+    .areas(main);
+
+    // group tree
+    let rows = group_rows(db, &self.collapsed);
+    let items: Vec<ListItem> = rows
+      .iter()
+      .map(|row| {
+        let marker = match (row.has_groups, self.collapsed.contains(&row.uuid)) {
+          (false, _) => "  ",
+          (true, false) => "▾ ",
+          (true, true) => "▸ ",
+        };
+        ListItem::new(Line::from(vec![
+          Span::raw("  ".repeat(row.depth)),
+          Span::raw(marker),
+          Span::raw(row.name.clone()),
+          Span::raw(format!(" {}", row.entries)).dark_gray(),
+        ]))
+      })
+      .collect();
+    self
+      .group_state
+      .select(rows.iter().position(|r| r.uuid == self.group));
+    frame.render_stateful_widget(
+      List::new(items)
+        .block(pane_block("Groups", self.pane == Pane::Groups))
+        .highlight_style(highlight(self.pane == Pane::Groups)),
+      groups,
+      &mut self.group_state,
+    );
+
+    // entries of the group or search results
+    let list = entry_list(db, &self.group, &self.query);
+    let items: Vec<ListItem> = list
+      .iter()
+      .map(|(label, e)| {
+        let title = match is_expired(e) {
+          true => Span::raw(label.clone()).red(),
+          false => Span::raw(label.clone()),
+        };
+        let user = e.get_username().unwrap_or_default();
+        ListItem::new(Line::from(vec![
+          title,
+          Span::raw(format!("  {}", user)).dark_gray(),
+        ]))
+      })
+      .collect();
+    let title = match self.query.is_empty() {
+      true => "Entries".to_string(),
+      false => format!("Search \"{}\"", self.query),
+    };
+    self
+      .entry_state
+      .select(list.iter().position(|(_, e)| Some(e.uuid) == self.entry));
+    frame.render_stateful_widget(
+      List::new(items)
+        .block(pane_block(&title, self.pane == Pane::Entries))
+        .highlight_style(highlight(self.pane == Pane::Entries)),
+      entries,
+      &mut self.entry_state,
+    );
+
+    let entry = self
+      .entry
+      .and_then(|uuid| find_entry(db, &entry_name(&uuid)).ok());
+    let block = pane_block("Entry", self.pane == Pane::Fields);
+    match entry {
+      Some(entry) => draw_entry(
+        frame,
+        fields,
+        entry,
+        block,
+        self.reveal,
+        highlight(self.pane == Pane::Fields),
+        self.field,
+        &mut self.field_state,
+      ),
+      None => frame.render_widget(Paragraph::new("No entry").block(block), fields),
+    }
+
+    match &self.mode {
+      Mode::Edit { field, input } => {
+        draw_input(frame, main, &format!("Edit {}", field), input, false)
+      }
+      Mode::AddField { input } => draw_input(frame, main, "New field", input, false),
+      Mode::NewEntry { input } => draw_input(frame, main, "New entry", input, false),
+      _ => {}
+    }
+  }
+  fn draw_status(&self, frame: &mut Frame, area: Rect) {
+    let line = match (&self.mode, &self.status) {
+      (Mode::Search, _) => {
+        frame.set_cursor_position(Position::new(
+          area.x + 1 + self.query.chars().count() as u16,
+          area.y,
+        ));
+        Line::from(format!("/{}", self.query))
+      }
+      (Mode::ConfirmDelete, _) => {
+        let title = self
+          .selected_entry()
+          .and_then(|e| e.get_title())
+          .unwrap_or_default();
+        Line::from(format!("Delete \"{}\"? (y/n)", title)).yellow()
+      }
+      (_, Some(status)) if status.error => Line::from(status.message.clone()).red(),
+      (_, Some(status)) => Line::from(status.message.clone()).green(),
+      (Mode::Normal, None) => Line::from(
+        "q quit  / search  tab pane  c copy  u user  o otp  r reveal  e edit  a add field  \
+         x remove field  g generate  n new  d delete  L lock",
+      )
+      .dark_gray(),
+      (Mode::Edit { .. }, None) => {
+        Line::from("enter save  alt+enter new line  esc cancel").dark_gray()
+      }
+      (Mode::Locked { .. }, None) => Line::from("enter unlock  esc quit").dark_gray(),
+      (_, None) => Line::from("enter confirm  esc cancel").dark_gray(),
+    };
+    frame.render_widget(Paragraph::new(line), area);
+  }
+}
+
+/// The fields of an entry, its tags and times and the current one time password.
+#[allow(clippy::too_many_arguments)]
+fn draw_entry(
+  frame: &mut Frame,
+  area: Rect,
+  entry: &Entry,
+  block: Block,
+  reveal: bool,
+  highlight: Style,
+  selected: usize,
+  state: &mut TableState,
+) {
+  let otp = entry
+    .get(OTP_FIELD)
+    .and_then(|secret| otp_details(secret.to_string(), None, None).ok());
+  let [table_area, info_area, otp_area] = Layout::vertical([
+    Constraint::Min(0),
+    Constraint::Length(2),
+    Constraint::Length(if otp.is_some() { 3 } else { 0 }),
+  ])
+  .areas(area);
+
+  let rows: Vec<Row> = field_names(entry)
+    .into_iter()
+    .map(|name| {
+      let value = match entry.fields.get(&name) {
+        Some(Value::Protected(_)) if !reveal => HIDDEN.to_string(),
+        _ => preview(entry.get(&name).unwrap_or_default()),
+      };
+      Row::new(vec![Span::raw(name).bold(), Span::raw(value)])
+    })
+    .collect();
+  state.select(Some(selected));
+  frame.render_stateful_widget(
+    Table::new(rows, [Constraint::Length(12), Constraint::Min(0)])
+      .block(block)
+      .row_highlight_style(highlight),
+    table_area,
+    state,
+  );
+
+  let format_time = |time: Option<chrono::NaiveDateTime>| {
+    time.map_or("-".to_string(), |t| t.format("%Y-%m-%d %H:%M").to_string())
+  };
+  let info = vec![
+    Line::from(format!(" Tags: {}", entry.tags.join(", "))),
+    Line::from(format!(
+      " Modified: {}  Expires: {}",
+      format_time(entry.times.last_modification),
+      format_time(entry_expiry(entry))
+    )),
+  ];
+  frame.render_widget(Paragraph::new(info).dark_gray(), info_area);
+
+  if let Some(otp) = otp {
+    frame.render_widget(
+      Gauge::default()
+        .block(Block::bordered().title("One time password"))
+        .gauge_style(Style::default().fg(Color::Cyan))
+        .ratio(otp.expires_in as f64 / otp.period.max(1) as f64)
+        .label(format!("{}  {}s", otp.code, otp.expires_in)),
+      otp_area,
+    );
+  }
+}
+
+fn draw_locked(frame: &mut Frame, area: Rect, url: &str, input: &Input) {
+  let name = url.rsplit('/').next().unwrap_or(url);
+  let masked = Input {
+    value: "*".repeat(input.value.chars().count()),
+    cursor: input.value[..input.cursor].chars().count(),
+  };
+  draw_input(frame, area, &format!("{} is locked", name), &masked, true);
+}
+
+/// Draws an input as a popup in the middle of `area`, with the cursor in it.
+fn draw_input(frame: &mut Frame, area: Rect, title: &str, input: &Input, password: bool) {
+  let lines = input.value.lines().count().max(1) as u16;
+  let width = (area.width * 3 / 5).max(20).min(area.width);
+  let height = (lines + 2).min(area.height);
+  let popup = Rect {
+    x: area.x + (area.width - width) / 2,
+    y: area.y + (area.height - height) / 2,
+    width,
+    height,
+  };
+
+  let (line, column) = input.position();
+  let inner_width = width.saturating_sub(2);
+  let inner_height = height.saturating_sub(2);
+  let scroll = (
+    line.saturating_sub(inner_height.saturating_sub(1)),
+    column.saturating_sub(inner_width.saturating_sub(1)),
+  );
+  let block = match password {
+    true => Block::bordered()
+      .title(title.to_string())
+      .title_bottom("Password"),
+    false => Block::bordered().title(title.to_string()),
+  };
+
+  frame.render_widget(Clear, popup);
+  frame.render_widget(
+    Paragraph::new(input.value.as_str())
+      .block(block.border_style(Style::default().fg(Color::Cyan)))
+      .scroll(scroll),
+    popup,
+  );
+  frame.set_cursor_position(Position::new(
+    popup.x + 1 + column - scroll.1,
+    popup.y + 1 + line - scroll.0,
+  ));
+}
+
+fn pane_block(title: &str, focused: bool) -> Block<'static> {
+  let block = Block::bordered().title(title.to_string());
+  match focused {
+    true => block.border_style(Style::default().fg(Color::Cyan)),
+    false => block.border_style(Style::default().fg(Color::DarkGray)),
+  }
+}
+
+fn highlight(focused: bool) -> Style {
+  match focused {
+    true => Style::default().add_modifier(Modifier::REVERSED),
+    false => Style::default().add_modifier(Modifier::BOLD),
+  }
+}
+
+/// First line of a value, marking that there is more.
+fn preview(value: &str) -> String {
+  match value.split_once('\n') {
+    Some((first, _)) => format!("{} …", first),
+    None => value.to_string(),
+  }
+}
+
+fn entry_name(uuid: &Uuid) -> String {
+  format!("{}{}", UUID_PREFIX, uuid)
+}
+
+/// The group tree below the root, without the subgroups of collapsed groups.
+fn group_rows(db: &Database, collapsed: &HashSet<Uuid>) -> Vec<GroupRow> {
+  let mut rows = Vec::new();
+  collect_group_rows(&db.root, 0, collapsed, &mut rows);
+  rows
+}
+
+fn collect_group_rows(
+  group: &Group,
+  depth: usize,
+  collapsed: &HashSet<Uuid>,
+  rows: &mut Vec<GroupRow>,
+) {
+  rows.push(GroupRow {
+    uuid: group.uuid,
+    depth,
+    name: group.name.clone(),
+    entries: group.entries.len(),
+    has_groups: !group.groups.is_empty(),
+  });
+  if !collapsed.contains(&group.uuid) {
+    for g in group.groups.iter() {
+      collect_group_rows(g, depth + 1, collapsed, rows);
+    }
+  }
+}
+
+/// Entries of the group by title, or every entry matching `query` by path.
+fn entry_list<'a>(
+  db: &'a Database,
+  group: &Uuid,
+  query: &str,
+) -> Vec<(String, &'a Entry)> {
+  if !query.is_empty() {
+    return search_entries(db, query);
+  }
+  find_group(&db.root, group)
+    .map(|g| {
+      g.entries
+        .iter()
+        .map(|e| (e.get_title().unwrap_or_default().to_string(), e))
+        .collect()
+    })
+    .unwrap_or_default()
+}
+
+/// The standard fields, then all others by name.
+fn field_names(entry: &Entry) -> Vec<String> {
+  let mut other: Vec<String> = entry
+    .fields
+    .keys()
+    .filter(|k| !STANDARD_FIELDS.contains(&k.as_str()))
+    .cloned()
+    .collect();
+  other.sort();
+  STANDARD_FIELDS
+    .iter()
+    .map(|f| f.to_string())
+    .chain(other)
+    .collect()
+}
+
+/// Sets a field, keeping the previous version in the history. Passwords, one
+/// time passwords and fields that already were protected are stored protected.
+/// Returns whether the value changed.
+fn set_entry_field(
+  db: &mut Database,
+  uuid: &Uuid,
+  field: &str,
+  value: &str,
+) -> Result<bool> {
+  let entry = find_entry_mut(&mut db.root, &entry_name(uuid))?;
+  if entry.get(field) == Some(value)
+    || (value.is_empty() && !entry.fields.contains_key(field))
+  {
+    return Ok(false);
+  }
+  let protected = field == "Password"
+    || field == OTP_FIELD
+    || matches!(entry.fields.get(field), Some(Value::Protected(_)));
+  backup_entry(entry, &db.meta);
+  let value = match protected {
+    true => Value::protected(value),
+    false => Value::Unprotected(value.to_string()),
+  };
+  entry.fields.insert(field.to_string(), value);
+  Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+  use keepass::config::DatabaseConfig;
+
+  use super::*;
+  use crate::ensure_group;
+
+  fn key(code: KeyCode) -> KeyEvent {
+    KeyEvent::new(code, KeyModifiers::NONE)
+  }
+
+  fn ctrl(c: char) -> KeyEvent {
+    KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL)
+  }
+
+  fn database() -> Database {
+    let mut db = Database::new(DatabaseConfig::default());
+    ensure_group(&mut db.root, &["Prod"]);
+    new_entry(&mut db, "github", Some("tim"), None).unwrap();
+    new_entry(&mut db, "Prod/db", Some("admin"), None).unwrap();
+    db
+  }
+
+  fn uuid(db: &Database, name: &str) -> Uuid {
+    find_entry(db, name).unwrap().uuid
+  }
+
+  #[test]
+  fn input_edits_at_the_cursor() {
+    let mut input = Input::new("héllo");
+    input.handle(key(KeyCode::Left));
+    input.handle(key(KeyCode::Left));
+    input.handle(key(KeyCode::Backspace));
+    input.handle(key(KeyCode::Char('X')));
+    assert_eq!(input.value, "héXlo");
+
+    input.handle(key(KeyCode::Home));
+    input.handle(key(KeyCode::Right));
+    input.handle(key(KeyCode::Delete));
+    assert_eq!(input.value, "hXlo");
+
+    input.handle(key(KeyCode::End));
+    input.handle(ctrl('u'));
+    assert_eq!(input.value, "");
+    assert_eq!(input.cursor, 0);
+    // nothing to remove or move over at the start
+    input.handle(key(KeyCode::Backspace));
+    input.handle(key(KeyCode::Left));
+    assert_eq!(input.cursor, 0);
+  }
+
+  #[test]
+  fn input_ignores_control_characters() {
+    let mut input = Input::new("ab");
+    input.handle(ctrl('x'));
+    input.handle(key(KeyCode::Tab));
+    assert_eq!(input.value, "ab");
+  }
+
+  #[test]
+  fn input_position_counts_lines_and_characters() {
+    let mut input = Input::new("first\nsé");
+    assert_eq!(input.position(), (1, 2));
+    input.handle(key(KeyCode::Home));
+    assert_eq!(input.position(), (0, 0));
+    input.insert('\n');
+    assert_eq!(input.position(), (1, 0));
+  }
+
+  #[test]
+  fn field_names_start_with_the_standard_fields() {
+    let mut db = database();
+    let entry = find_entry_mut(&mut db.root, "github").unwrap();
+    for field in ["pin", "Api Key"] {
+      entry
+        .fields
+        .insert(field.to_string(), Value::unprotected("x"));
+    }
+    assert_eq!(
+      field_names(entry),
+      vec!["Title", "UserName", "Password", "URL", "Notes", "Api Key", "pin"]
+    );
+  }
+
+  #[test]
+  fn set_entry_field_keeps_history_and_protection() {
+    let mut db = database();
+    let github = uuid(&db, "github");
+
+    assert!(set_entry_field(&mut db, &github, "Password", "secret").unwrap());
+    assert!(!set_entry_field(&mut db, &github, "Password", "secret").unwrap());
+    // empty values do not add a field
+    assert!(!set_entry_field(&mut db, &github, "pin", "").unwrap());
+    assert!(set_entry_field(&mut db, &github, "region", "eu").unwrap());
+
+    let entry = find_entry(&db, "github").unwrap();
+    assert!(entry.fields["Password"].is_protected());
+    assert!(!entry.fields["region"].is_protected());
+    assert!(!entry.fields.contains_key("pin"));
+    let history = entry.history.as_ref().unwrap().get_entries();
+    assert_eq!(history.len(), 2);
+    assert_eq!(history[0].get("region"), None);
+  }
+
+  #[tokio::test]
+  async fn lock_keeps_an_edit_in_progress() {
+    let path = std::env::temp_dir().join(format!("key-tui-{}.kdbx", Uuid::new_v4()));
+    let options = KeeOptions {
+      keepassdb: format!("file://{}", path.display()),
+      keepassdb_keyfile: None,
+      keepassdb_password: Some("test".to_string()),
+      s3_access_key: None,
+      s3_secret_key: None,
+      default_field: "Password".to_string(),
+      clipboard_timeout: None,
+      lock_timeout: None,
+      generator: None,
+    };
+    let key = get_database_key(&options).unwrap();
+    let mut db = database();
+    write_database(&options, &mut db, &key).await.unwrap();
+
+    let copy = |_: String| Ok(());
+    let mut app = App::new(&options, db, key, 0, &copy);
+    app.mode = Mode::Edit {
+      field: "Notes".to_string(),
+      input: Input::new("half typed"),
+    };
+    app.lock().await.unwrap();
+    assert!(app.db.is_none());
+    assert!(matches!(app.mode, Mode::Locked { .. }));
+
+    // on_key takes the mode out before unlocking
+    app.mode = Mode::Normal;
+    let unlocked = app.unlock("test").await;
+    std::fs::remove_file(&path).unwrap();
+    unlocked.unwrap();
+    match &app.mode {
+      Mode::Edit { field, input } => {
+        assert_eq!(field, "Notes");
+        assert_eq!(input.value, "half typed");
+      }
+      _ => panic!("the edit was not resumed"),
+    }
+  }
+
+  #[test]
+  fn entry_list_shows_the_group_or_search_results() {
+    let db = database();
+    let titles = |group: &Uuid, query: &str| -> Vec<String> {
+      entry_list(&db, group, query)
+        .into_iter()
+        .map(|(name, _)| name)
+        .collect()
+    };
+    let prod = db.root.groups[0].uuid;
+
+    assert_eq!(titles(&db.root.uuid, ""), vec!["github"]);
+    assert_eq!(titles(&prod, ""), vec!["db"]);
+    // search goes through all groups and shows paths
+    assert_eq!(titles(&db.root.uuid, "ADMIN"), vec!["Prod/db"]);
+    assert!(titles(&Uuid::new_v4(), "").is_empty());
+  }
+}
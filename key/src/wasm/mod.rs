@@ -1,7 +1,5 @@
 use wasm_bindgen::prelude::*;
 
-use crate::key;
-
 #[wasm_bindgen]
 extern "C" {
   #[wasm_bindgen(js_namespace = console)]
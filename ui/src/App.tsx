@@ -20,13 +20,31 @@ type Entry = {
   uuid: string;
   title: string | undefined;
   user: string | undefined;
-  password: string | undefined;
+  website: string | undefined;
+};
+
+type Field = {
+  value: string | null;
+  protected: boolean;
+};
+
+type EntryDetail = Entry & {
+  path: string;
+  fields: Record<string, Field>;
+  tags: string[];
+  notes: string | null;
+  expires: boolean;
+  expiry_time: string | null;
+  created: string | null;
+  modified: string | null;
+  accessed: string | null;
+  history_items: number;
 };
 
 function ListView() {
   const [list, setList] = createSignal<(Group | Entry)[]>([]);
   const [selected, setSelected] = createSignal<string>();
-  const [detail, setDetail] = createSignal<Entry | Group>();
+  const [detail, setDetail] = createSignal<EntryDetail>();
   const [filterValue, setFilterValue] = createSignal<string>("");
 
   createEffect(() => {
@@ -34,8 +52,8 @@ function ListView() {
     if (!id) return;
 
     if (isTauri()) {
-      invoke("entry", { name: id }).then((res) => {
-        setDetail(res as Entry | Group);
+      invoke("entry", { name: id, reveal: true }).then((res) => {
+        setDetail(res as EntryDetail);
       });
     }
   });
@@ -81,7 +99,7 @@ function ListView() {
   );
 }
 
-function EntryView(props: { title?: string; data?: Entry | Group }) {
+function EntryView(props: { title?: string; data?: EntryDetail }) {
   return (
     <div>
       <div class="p-2 px-4 text-lg">{props.title}</div>
@@ -93,10 +111,15 @@ function EntryView(props: { title?: string; data?: Entry | Group }) {
             label="Password"
             password
             placeholder="Password"
-            value={props.data?.password}
+            value={props.data.fields.Password?.value ?? undefined}
           />
           <Input label="Website" placeholder="Website" value={props.data.website} />
-          <Input multiline label="Notes" placeholder="Notes" value={props.data.notes} />
+          <Input
+            multiline
+            label="Notes"
+            placeholder="Notes"
+            value={props.data.notes ?? undefined}
+          />
         </div>
       )}
     </div>